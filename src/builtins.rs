@@ -0,0 +1,105 @@
+use crate::environment::Environment;
+use crate::eval_value::{EvalValue, MapKey, NativeFunction};
+use crate::symbol::Symbol;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn define_globals(global_environment: &mut Environment) {
+    define_native(global_environment, NativeFunction::new("clock", 0, clock));
+    define_native(global_environment, NativeFunction::new("len", 1, len));
+    define_native(global_environment, NativeFunction::new("push", 2, push));
+    define_native(global_environment, NativeFunction::new("pop", 1, pop));
+    define_native(global_environment, NativeFunction::new("keys", 1, keys));
+    define_native(global_environment, NativeFunction::new("values", 1, values));
+    define_native(global_environment, NativeFunction::new("has", 2, has));
+    define_native(global_environment, NativeFunction::new("remove", 2, remove));
+}
+
+fn define_native(global_environment: &mut Environment, native_function: NativeFunction) {
+    let name = Symbol::intern(&native_function.name);
+    global_environment.define_var(name, EvalValue::NativeFunction(Rc::new(native_function)));
+}
+
+fn clock(_arguments: &[EvalValue]) -> Result<EvalValue, String> {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => Ok(EvalValue::Number(elapsed.as_secs_f64())),
+        Err(e) => Err(format!("System clock is before the unix epoch: {}", e)),
+    }
+}
+
+fn len(arguments: &[EvalValue]) -> Result<EvalValue, String> {
+    match &arguments[0] {
+        EvalValue::List(list) => Ok(EvalValue::Int(list.borrow().len() as i64)),
+        EvalValue::Map(map) => Ok(EvalValue::Int(map.borrow().len() as i64)),
+        EvalValue::Str(s) => Ok(EvalValue::Int(s.chars().count() as i64)),
+        _ => Err("len() expects a list, map or string".to_string()),
+    }
+}
+
+fn push(arguments: &[EvalValue]) -> Result<EvalValue, String> {
+    match &arguments[0] {
+        EvalValue::List(list) => {
+            list.borrow_mut().push(arguments[1].clone());
+            Ok(EvalValue::Nil)
+        }
+        _ => Err("push() expects a list".to_string()),
+    }
+}
+
+fn pop(arguments: &[EvalValue]) -> Result<EvalValue, String> {
+    match &arguments[0] {
+        EvalValue::List(list) => match list.borrow_mut().pop() {
+            Some(value) => Ok(value),
+            None => Err("pop() from empty list".to_string()),
+        },
+        _ => Err("pop() expects a list".to_string()),
+    }
+}
+
+fn map_key(key: &EvalValue) -> Result<MapKey, String> {
+    match MapKey::from_value(key) {
+        Some(key) => Ok(key),
+        None => Err("Map keys must be strings, numbers, bools or nil".to_string()),
+    }
+}
+
+fn new_list(elements: Vec<EvalValue>) -> EvalValue {
+    EvalValue::List(Rc::new(RefCell::new(elements)))
+}
+
+fn keys(arguments: &[EvalValue]) -> Result<EvalValue, String> {
+    match &arguments[0] {
+        EvalValue::Map(map) => Ok(new_list(
+            map.borrow().keys().map(|k| k.to_value()).collect(),
+        )),
+        _ => Err("keys() expects a map".to_string()),
+    }
+}
+
+fn values(arguments: &[EvalValue]) -> Result<EvalValue, String> {
+    match &arguments[0] {
+        EvalValue::Map(map) => Ok(new_list(map.borrow().values().cloned().collect())),
+        _ => Err("values() expects a map".to_string()),
+    }
+}
+
+fn has(arguments: &[EvalValue]) -> Result<EvalValue, String> {
+    match &arguments[0] {
+        EvalValue::Map(map) => {
+            let key = map_key(&arguments[1])?;
+            Ok(EvalValue::Bool(map.borrow().contains_key(&key)))
+        }
+        _ => Err("has() expects a map".to_string()),
+    }
+}
+
+fn remove(arguments: &[EvalValue]) -> Result<EvalValue, String> {
+    match &arguments[0] {
+        EvalValue::Map(map) => {
+            let key = map_key(&arguments[1])?;
+            Ok(map.borrow_mut().remove(&key).unwrap_or(EvalValue::Nil))
+        }
+        _ => Err("remove() expects a map".to_string()),
+    }
+}
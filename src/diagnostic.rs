@@ -0,0 +1,286 @@
+use crate::error::{ErrorDetail, Hint, LoxError};
+
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+struct Style {
+    color: bool,
+}
+
+impl Style {
+    fn paint(&self, code: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", code, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+// Where an error points in the source, 'underline' is the number of
+// characters to mark starting at 'column'
+struct Position<'a> {
+    line: u32,
+    column: Option<u32>,
+    source_line: Option<&'a str>,
+    underline: usize,
+}
+
+// Renders an error in the style of rustc:
+//
+// error: Must be numbers
+//  --> script.lox:1:7
+//   |
+// 1 | print 1 - "one";
+//   |       ^^^^^^^^^
+pub fn render(error: &LoxError, file_name: &str, source: &str, color: bool) -> String {
+    let style = Style { color };
+    let detail = error.detail();
+    let mut out = String::new();
+
+    out += &format!(
+        "{}{}\n",
+        style.paint(RED, "error"),
+        style.paint(BOLD, &format!(": {}", detail.message))
+    );
+
+    let position = find_position(detail, source);
+    let gutter_width = position
+        .as_ref()
+        .map_or(0, |position| position.line.to_string().len());
+    let gutter = " ".repeat(gutter_width);
+
+    match &position {
+        None => out += &format!("{}{} {}\n", gutter, style.paint(BLUE, "-->"), file_name),
+        Some(position) => {
+            let location = match position.column {
+                Some(column) => format!("{}:{}:{}", file_name, position.line, column),
+                None => format!("{}:{}", file_name, position.line),
+            };
+            out += &format!("{}{} {}\n", gutter, style.paint(BLUE, "-->"), location);
+
+            if let Some(source_line) = position.source_line {
+                let bar = style.paint(BLUE, "|");
+                out += &format!("{} {}\n", gutter, bar);
+                out += &format!(
+                    "{} {} {}\n",
+                    style.paint(BLUE, &position.line.to_string()),
+                    bar,
+                    source_line
+                );
+
+                if let Some(column) = position.column {
+                    // keep tabs so the carets line up with the source above
+                    let padding: String = source_line
+                        .chars()
+                        .take(column as usize - 1)
+                        .map(|c| if c == '\t' { '\t' } else { ' ' })
+                        .collect();
+                    let carets = "^".repeat(position.underline.max(1));
+                    out += &format!(
+                        "{} {} {}{}\n",
+                        gutter,
+                        bar,
+                        padding,
+                        style.paint(RED, &carets)
+                    );
+                }
+            }
+        }
+    }
+
+    for hint in &detail.hints {
+        let (label, text) = match hint {
+            Hint::Note(text) => ("note", text),
+            Hint::Help(text) => ("help", text),
+        };
+        out += &format!(
+            "{} {} {}: {}\n",
+            gutter,
+            style.paint(BLUE, "="),
+            label,
+            text
+        );
+    }
+
+    // runaway recursion repeats the same frame hundreds of times
+    let mut frames = detail.trace.iter().peekable();
+    while let Some(frame) = frames.next() {
+        out += &format!("{}\n", frame);
+
+        let mut repeated = 0;
+        while frames.next_if_eq(&frame).is_some() {
+            repeated += 1;
+        }
+        if repeated > 0 {
+            out += &format!("[previous line repeated {} more times]\n", repeated);
+        }
+    }
+
+    out
+}
+
+fn find_position<'a>(detail: &ErrorDetail, source: &'a str) -> Option<Position<'a>> {
+    // an empty span at the start of the source means the node had no position
+    let span = detail
+        .span
+        .filter(|span| span.end > 0 && span.end <= source.len())
+        .filter(|span| source.is_char_boundary(span.start) && source.is_char_boundary(span.end));
+
+    if let Some(span) = span {
+        let line = source[..span.start].matches('\n').count() as u32 + 1;
+        // the span belongs to some other source, as with a function defined
+        // in an earlier line of the REPL
+        if detail.line.is_some_and(|expected| expected != line) {
+            return find_line(detail, source);
+        }
+
+        let line_start = source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[span.start..]
+            .find('\n')
+            .map_or(source.len(), |i| span.start + i);
+        let column = source[line_start..span.start].chars().count() as u32 + 1;
+
+        // spans running over several lines are only marked up to the end of the first
+        let underline = source[span.start..span.end.min(line_end)].chars().count();
+
+        return Some(Position {
+            line,
+            column: Some(column),
+            source_line: Some(&source[line_start..line_end]),
+            underline,
+        });
+    }
+
+    find_line(detail, source)
+}
+
+fn find_line<'a>(detail: &ErrorDetail, source: &'a str) -> Option<Position<'a>> {
+    detail.line.map(|line| Position {
+        line,
+        column: None,
+        source_line: source.lines().nth((line as usize).saturating_sub(1)),
+        underline: 0,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::environment::Environment;
+    use crate::error::{new_runtime_error, ErrorKind, Frame};
+    use crate::interpreter::InterpreterContext;
+    use crate::token::Span;
+    use crate::{parser, resolver, scanner};
+
+    fn run(source: &str) -> LoxError {
+        let tokens = scanner::scan(source).unwrap();
+        let stmts = parser::parse(&tokens).unwrap();
+        resolver::resolve(&stmts).unwrap();
+
+        let mut global_environment = Environment::new();
+        let mut output = std::io::sink();
+        let mut interpreter = InterpreterContext::new(&mut global_environment, &mut output);
+        interpreter.interpret(&stmts).unwrap_err()
+    }
+
+    #[test]
+    fn snippet_with_carets() {
+        let source = "var a = 1;\nprint a - \"one\";\n";
+        let error = new_runtime_error(ErrorKind::TypeMismatch, "Must be numbers".to_string(), 2)
+            .with_default_span(Span { start: 17, end: 26 });
+
+        assert_eq!(
+            render(&error, "script.lox", source, false),
+            "error: Must be numbers\n \
+              --> script.lox:2:7\n  \
+               |\n\
+             2 | print a - \"one\";\n  \
+               |       ^^^^^^^^^\n"
+        );
+    }
+
+    #[test]
+    fn notes_and_help() {
+        let error = LoxError::Parse(Box::new(ErrorDetail {
+            hints: vec![
+                Hint::Note("a note".to_string()),
+                Hint::Help("some help".to_string()),
+            ],
+            ..ErrorDetail::new(ErrorKind::UnexpectedEof, "Expected ';'".to_string())
+        }));
+
+        assert_eq!(
+            render(&error, "script.lox", "", false),
+            "error: Expected ';'\n\
+             --> script.lox\n \
+             = note: a note\n \
+             = help: some help\n"
+        );
+    }
+
+    #[test]
+    fn line_without_span() {
+        let source = "print 1;\n\tprint -\"x\";";
+        let error = new_runtime_error(ErrorKind::TypeMismatch, "Negate".to_string(), 2);
+
+        assert_eq!(
+            render(&error, "script.lox", source, false),
+            "error: Negate\n \
+              --> script.lox:2\n  \
+               |\n\
+             2 | \tprint -\"x\";\n"
+        );
+    }
+
+    #[test]
+    fn traceback() {
+        let source = "fun inner() {\n  return -nil;\n}\n\
+                      fun outer() {\n  inner();\n}\n\
+                      var f = fun () { return outer(); };\n\
+                      f();";
+        let error = run(source);
+
+        assert_eq!(
+            render(&error, "script.lox", source, false),
+            "error: Unary negate expected number\n \
+              --> script.lox:2:10\n  \
+               |\n\
+             2 |   return -nil;\n  \
+               |          ^^^^\n\
+             [line 2] in inner\n\
+             [line 5] in outer\n\
+             [line 7] in <anonymous>\n\
+             [line 8] in script\n"
+        );
+    }
+
+    #[test]
+    fn repeated_frames() {
+        let mut error =
+            new_runtime_error(ErrorKind::StackOverflow, "Stack overflow".to_string(), 2);
+        let f = Frame {
+            function: "f".to_string(),
+            line: Some(2),
+        };
+        error.detail_mut().trace = vec![f.clone(), f.clone(), f];
+
+        assert_eq!(
+            render(&error, "script.lox", "", false),
+            "error: Stack overflow\n \
+              --> script.lox:2\n\
+             [line 2] in f\n\
+             [previous line repeated 2 more times]\n"
+        );
+    }
+
+    #[test]
+    fn colored() {
+        let error = new_runtime_error(ErrorKind::TypeMismatch, "Negate".to_string(), 1);
+        let rendered = render(&error, "script.lox", "-x", true);
+
+        assert!(rendered.starts_with("\x1b[1;31merror\x1b[0m"));
+    }
+}
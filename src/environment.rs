@@ -1,95 +1,108 @@
 use crate::eval_value::EvalValue;
-use std::cell::{RefCell, RefMut};
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+use crate::symbol::Symbol;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
 use std::rc::Rc;
 
-#[derive(Debug, Clone)]
-struct StackValue {
-    hash: u64,
-    value: Rc<RefCell<EvalValue>>,
+// A single scope. Scopes are shared through Rc so a closure holds on to the
+// scope it was declared in, and sees later declarations and assignments in it.
+pub struct Environment {
+    variables: RefCell<Variables>,
+    enclosing: Option<Rc<Environment>>,
 }
 
-#[derive(Debug, Clone)]
-pub struct Environment {
-    values: Vec<StackValue>,
-    scope_stack: Vec<usize>,
+// Values in the order they were defined, with the index of each name's slot
+// so lookups don't scan scopes with thousands of names
+#[derive(Default)]
+struct Variables {
+    values: Vec<(Symbol, EvalValue)>,
+    slots: HashMap<Symbol, usize>,
 }
 
 impl Environment {
     pub fn new() -> Environment {
-        Environment {
-            values: vec![],
-            scope_stack: vec![],
-        }
+        Environment::new_enclosed(None)
     }
 
-    pub fn new_capture_env(enclosing: &Environment) -> Environment {
+    pub fn new_enclosed(enclosing: Option<Rc<Environment>>) -> Environment {
         Environment {
-            values: enclosing.values.clone(),
-            scope_stack: vec![enclosing.values.len()],
+            variables: RefCell::new(Variables::default()),
+            enclosing,
         }
     }
 
-    pub fn define_var(&mut self, name: &str, value: EvalValue) {
-        let name_hash = Environment::hash_name(name);
-        let bottom = *self.scope_stack.last().unwrap_or(&0);
-
-        for stack_value in self.values[bottom..].iter_mut().rev() {
-            if stack_value.hash == name_hash {
-                stack_value.value = Rc::new(RefCell::new(value));
-                return;
+    pub fn define_var(&self, name: Symbol, value: EvalValue) {
+        let mut variables = self.variables.borrow_mut();
+        match variables.slots.get(&name) {
+            Some(&slot) => variables.values[slot].1 = value,
+            None => {
+                let slot = variables.values.len();
+                variables.values.push((name, value));
+                variables.slots.insert(name, slot);
             }
         }
+    }
 
-        self.values.push(StackValue {
-            hash: Environment::hash_name(name),
-            value: Rc::new(RefCell::new(value)),
-        });
+    pub fn get_var(&self, name: Symbol) -> Option<EvalValue> {
+        let variables = self.variables.borrow();
+        let slot = *variables.slots.get(&name)?;
+        Some(variables.values[slot].1.clone())
     }
 
-    pub fn get_var(&self, name: &str) -> Option<EvalValue> {
-        self.find_eval_value(name, 0)
-            .map(|ref_mut_value| ref_mut_value.clone())
+    // every variable defined in this scope, in the order they were defined
+    pub fn entries(&self) -> Vec<(Symbol, EvalValue)> {
+        self.variables.borrow().values.clone()
     }
 
-    pub fn set_var(&mut self, name: &str, value: EvalValue) {
-        {
-            if let Some(mut mut_ref_eval_value) = self.find_eval_value(name, 0) {
-                *mut_ref_eval_value = value;
-                return;
+    // returns false if the variable was never defined in this scope
+    pub fn assign_var(&self, name: Symbol, value: EvalValue) -> bool {
+        let mut variables = self.variables.borrow_mut();
+        match variables.slots.get(&name) {
+            Some(&slot) => {
+                variables.values[slot].1 = value;
+                true
             }
+            None => false,
         }
-
-        self.values.push(StackValue {
-            hash: Environment::hash_name(name),
-            value: Rc::new(RefCell::new(value)),
-        });
     }
 
-    pub fn push_scope(&mut self) {
-        self.scope_stack.push(self.values.len());
+    // 'depth' is the number of scopes to walk out, as computed by the resolver
+    pub fn get_at(&self, depth: usize, name: Symbol) -> Option<EvalValue> {
+        self.ancestor(depth)?.get_var(name)
     }
 
-    pub fn pop_scope(&mut self) {
-        self.values.truncate(*self.scope_stack.last().unwrap_or(&0));
-    }
-
-    fn hash_name(name: &str) -> u64 {
-        let mut hasher = DefaultHasher::new();
-        name.hash(&mut hasher);
-        hasher.finish()
+    pub fn assign_at(&self, depth: usize, name: Symbol, value: EvalValue) -> bool {
+        match self.ancestor(depth) {
+            Some(environment) => environment.assign_var(name, value),
+            None => false,
+        }
     }
 
-    fn find_eval_value(&self, name: &str, stack_bottom_idx: usize) -> Option<RefMut<EvalValue>> {
-        let name_hash = Environment::hash_name(name);
-
-        for stack_value in self.values[stack_bottom_idx..].iter().rev() {
-            if stack_value.hash == name_hash {
-                return Some(stack_value.value.borrow_mut());
-            }
+    fn ancestor(&self, depth: usize) -> Option<&Environment> {
+        let mut environment = self;
+        for _ in 0..depth {
+            environment = environment.enclosing.as_deref()?;
         }
+        Some(environment)
+    }
+}
 
-        None
+// Only the names are printed, values can hold closures that point back at this scope
+impl fmt::Debug for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Environment")
+            .field(
+                "names",
+                &self
+                    .variables
+                    .borrow()
+                    .values
+                    .iter()
+                    .map(|(symbol, _)| *symbol)
+                    .collect::<Vec<Symbol>>(),
+            )
+            .field("enclosing", &self.enclosing)
+            .finish()
     }
 }
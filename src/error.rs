@@ -0,0 +1,181 @@
+use crate::token::{Span, Token};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    // reading the source or writing output
+    Io,
+
+    // scanning
+    InvalidCharacter,
+    InvalidNumber,
+
+    // parsing
+    UnexpectedToken,
+    UnexpectedEof,
+    InvalidAssignmentTarget,
+    LoopControlOutsideLoop,
+
+    // resolving
+    ReadInOwnInitializer,
+    Redeclaration,
+    InvalidReturn,
+    InvalidThis,
+    InvalidSuper,
+
+    // running
+    UndefinedVariable,
+    UndefinedProperty,
+    TypeMismatch,
+    IntegerOverflow,
+    DivisionByZero,
+    NotCallable,
+    ArityMismatch,
+    StackOverflow,
+    InvalidIndex,
+    IndexOutOfRange,
+    MissingKey,
+    InvalidInheritance,
+    NativeError,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorDetail {
+    pub kind: ErrorKind,
+    pub message: String,
+    // the token the error was reported at, for errors that name one
+    pub lexeme: Option<String>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    pub span: Option<Span>,
+    pub hints: Vec<Hint>,
+    // the calls a runtime error unwound through, innermost first
+    pub trace: Vec<Frame>,
+}
+
+// Extra lines shown under the source snippet by the diagnostics renderer
+#[derive(Debug, Clone, PartialEq)]
+pub enum Hint {
+    Note(String),
+    Help(String),
+}
+
+impl ErrorDetail {
+    pub fn new(kind: ErrorKind, message: String) -> ErrorDetail {
+        ErrorDetail {
+            kind,
+            message,
+            lexeme: None,
+            line: None,
+            column: None,
+            span: None,
+            hints: vec![],
+            trace: vec![],
+        }
+    }
+
+    pub fn at_line(kind: ErrorKind, message: String, line: u32) -> ErrorDetail {
+        ErrorDetail {
+            line: Some(line),
+            ..ErrorDetail::new(kind, message)
+        }
+    }
+
+    pub fn at_token(kind: ErrorKind, message: String, token: &Token) -> ErrorDetail {
+        ErrorDetail {
+            column: Some(token.column).filter(|column| *column > 0),
+            span: Some(token.span),
+            ..ErrorDetail::at_lexeme(kind, message, &token.lexeme(), token.line)
+        }
+    }
+
+    pub fn at_lexeme(kind: ErrorKind, message: String, lexeme: &str, line: u32) -> ErrorDetail {
+        ErrorDetail {
+            lexeme: Some(lexeme.to_string()),
+            line: Some(line),
+            ..ErrorDetail::new(kind, message)
+        }
+    }
+}
+
+// A function that was running when a runtime error happened, 'line' is
+// where execution was inside it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub function: String,
+    pub line: Option<u32>,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "[line {}] in {}", line, self.function),
+            None => write!(f, "in {}", self.function),
+        }
+    }
+}
+
+// An error from any phase of running a lox program
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoxError {
+    Io(Box<ErrorDetail>),
+    Scan(Box<ErrorDetail>),
+    Parse(Box<ErrorDetail>),
+    Resolve(Box<ErrorDetail>),
+    Runtime(Box<ErrorDetail>),
+}
+
+impl LoxError {
+    pub fn detail(&self) -> &ErrorDetail {
+        match self {
+            LoxError::Io(detail)
+            | LoxError::Scan(detail)
+            | LoxError::Parse(detail)
+            | LoxError::Resolve(detail)
+            | LoxError::Runtime(detail) => detail,
+        }
+    }
+
+    pub fn detail_mut(&mut self) -> &mut ErrorDetail {
+        match self {
+            LoxError::Io(detail)
+            | LoxError::Scan(detail)
+            | LoxError::Parse(detail)
+            | LoxError::Resolve(detail)
+            | LoxError::Runtime(detail) => detail,
+        }
+    }
+
+    // Errors pick up the span of the innermost node they pass through,
+    // an outer node never replaces a span that is already there
+    pub fn with_default_span(mut self, span: Span) -> LoxError {
+        let detail = self.detail_mut();
+        if detail.span.is_none() {
+            detail.span = Some(span);
+        }
+
+        self
+    }
+}
+
+pub fn new_runtime_error(kind: ErrorKind, message: String, line: u32) -> LoxError {
+    LoxError::Runtime(Box::new(ErrorDetail::at_line(kind, message, line)))
+}
+
+impl fmt::Display for ErrorDetail {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.lexeme, self.line) {
+            (Some(lexeme), Some(line)) => {
+                write!(f, "Line {} at '{}': {}", line, lexeme, self.message)
+            }
+            (None, Some(line)) => write!(f, "{} at line {}", self.message, line),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl fmt::Display for LoxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.detail())
+    }
+}
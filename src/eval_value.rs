@@ -1,63 +1,388 @@
 use crate::environment::Environment;
-use crate::interpreter::InterpreterContext;
+use crate::error::LoxError;
+use crate::interpreter::{Completion, InterpreterContext};
 use crate::stmt;
+use crate::symbol;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::rc::Rc;
 
 #[derive(Debug)]
 pub struct LoxFunction {
     pub declaration: Rc<stmt::Function>,
-    pub closure: Option<Environment>,
+    pub closure: Option<Rc<Environment>>,
+    pub is_initializer: bool,
 }
 
 impl LoxFunction {
     pub fn call(
         lox_function: Rc<LoxFunction>,
-        global_environment: &mut Environment,
-        arguments: &Vec<EvalValue>,
-    ) -> Result<EvalValue, String> {
-        let mut environment = {
-            match &lox_function.closure {
-                None => Environment::new(),
-                Some(closure) => Environment::new_capture_env(&closure),
-            }
-        };
-
-        // allow recursion
-        environment.set_var(&lox_function.declaration.name, EvalValue::Function(lox_function.clone()));
+        interpreter: &mut InterpreterContext,
+        arguments: &[EvalValue],
+    ) -> Result<EvalValue, LoxError> {
+        let environment = Environment::new_enclosed(lox_function.closure.clone());
 
         let parameters = &lox_function.declaration.parameters;
         for arg in parameters.iter().zip(arguments.iter()) {
-            environment.set_var(arg.0, arg.1.clone());
+            environment.define_var(*arg.0, arg.1.clone());
+        }
+
+        let enclosing = interpreter.local_environment.replace(Rc::new(environment));
+        let result = interpreter.execute_many(&lox_function.declaration.statements);
+        interpreter.local_environment = enclosing;
+        let result = result?;
+
+        // an initializer always hands back the instance it was bound to
+        if lox_function.is_initializer {
+            if let Some(this) = lox_function
+                .closure
+                .as_ref()
+                .and_then(|c| c.get_var(symbol::THIS))
+            {
+                return Ok(this);
+            }
+        }
+
+        match result {
+            Some(Completion::Return(value)) => Ok(value),
+            _ => Ok(EvalValue::Nil),
+        }
+    }
+
+    pub fn bind(&self, instance: Rc<LoxInstance>) -> LoxFunction {
+        let environment = Environment::new_enclosed(self.closure.clone());
+        environment.define_var(symbol::THIS, EvalValue::Instance(instance));
+
+        LoxFunction {
+            declaration: self.declaration.clone(),
+            closure: Some(Rc::new(environment)),
+            is_initializer: self.is_initializer,
+        }
+    }
+}
+
+pub type NativeFn = dyn Fn(&[EvalValue]) -> Result<EvalValue, String>;
+
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: u32,
+    pub function: Box<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new(
+        name: &str,
+        arity: u32,
+        function: impl Fn(&[EvalValue]) -> Result<EvalValue, String> + 'static,
+    ) -> NativeFunction {
+        NativeFunction {
+            name: name.to_string(),
+            arity,
+            function: Box::new(function),
+        }
+    }
+
+    pub fn call(&self, arguments: &[EvalValue]) -> Result<EvalValue, String> {
+        (self.function)(arguments)
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NativeFunction")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub struct LoxClass {
+    pub name: String,
+    pub superclass: Option<Rc<LoxClass>>,
+    pub methods: HashMap<String, Rc<LoxFunction>>,
+}
+
+impl LoxClass {
+    pub fn find_method(&self, name: &str) -> Option<Rc<LoxFunction>> {
+        if let Some(method) = self.methods.get(name) {
+            return Some(method.clone());
+        }
+
+        match &self.superclass {
+            Some(superclass) => superclass.find_method(name),
+            None => None,
         }
+    }
+
+    pub fn arity(&self) -> u32 {
+        match self.find_method("init") {
+            Some(initializer) => initializer.declaration.arity(),
+            None => 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LoxInstance {
+    pub class: Rc<LoxClass>,
+    fields: RefCell<HashMap<String, EvalValue>>,
+}
+
+impl LoxInstance {
+    pub fn new(class: Rc<LoxClass>) -> LoxInstance {
+        LoxInstance {
+            class,
+            fields: RefCell::new(HashMap::new()),
+        }
+    }
 
-        let mut local_interpreter =
-            InterpreterContext::new_with_local_env(global_environment, environment);
-        if let Some(result) = local_interpreter.execute_many(&lox_function.declaration.statements)? {
-            return Ok(result);
-        } else {
-            return Ok(EvalValue::Nil);
+    pub fn get(instance: &Rc<LoxInstance>, name: &str) -> Option<EvalValue> {
+        if let Some(value) = instance.fields.borrow().get(name) {
+            return Some(value.clone());
         }
+
+        instance
+            .class
+            .find_method(name)
+            .map(|method| EvalValue::Function(Rc::new(method.bind(instance.clone()))))
+    }
+
+    pub fn set(&self, name: &str, value: EvalValue) {
+        self.fields.borrow_mut().insert(name.to_string(), value);
+    }
+}
+
+// The subset of values that can key a map. Floats with an integral value are
+// normalised to ints so that 1 and 1.0, or -0 and 0, are the same key, and NaN
+// is rejected as it is not equal to itself.
+// Keys are ordered nil < bools < numbers < strings so maps display deterministically.
+#[derive(Debug, Clone)]
+pub enum MapKey {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Number(f64),
+    Str(Rc<String>),
+}
+
+impl MapKey {
+    pub fn from_value(value: &EvalValue) -> Option<MapKey> {
+        match value {
+            EvalValue::Nil => Some(MapKey::Nil),
+            EvalValue::Bool(b) => Some(MapKey::Bool(*b)),
+            EvalValue::Int(n) => Some(MapKey::Int(*n)),
+            EvalValue::Number(n) if n.is_nan() => None,
+            // i64::MAX as f64 rounds up to 2^63, which is out of range
+            EvalValue::Number(n)
+                if n.fract() == 0.0 && *n >= i64::MIN as f64 && *n < i64::MAX as f64 =>
+            {
+                Some(MapKey::Int(*n as i64))
+            }
+            EvalValue::Number(n) => Some(MapKey::Number(*n)),
+            EvalValue::Str(s) => Some(MapKey::Str(s.clone())),
+            _ => None,
+        }
+    }
+
+    pub fn to_value(&self) -> EvalValue {
+        match self {
+            MapKey::Nil => EvalValue::Nil,
+            MapKey::Bool(b) => EvalValue::Bool(*b),
+            MapKey::Int(n) => EvalValue::Int(*n),
+            MapKey::Number(n) => EvalValue::Number(*n),
+            MapKey::Str(s) => EvalValue::Str(s.clone()),
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            MapKey::Nil => 0,
+            MapKey::Bool(_) => 1,
+            MapKey::Int(_) | MapKey::Number(_) => 2,
+            MapKey::Str(_) => 3,
+        }
+    }
+}
+
+impl PartialEq for MapKey {
+    fn eq(&self, other: &MapKey) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for MapKey {}
+
+impl PartialOrd for MapKey {
+    fn partial_cmp(&self, other: &MapKey) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for MapKey {
+    fn cmp(&self, other: &MapKey) -> Ordering {
+        match (self, other) {
+            (MapKey::Bool(l), MapKey::Bool(r)) => l.cmp(r),
+            (MapKey::Int(l), MapKey::Int(r)) => l.cmp(r),
+            (MapKey::Number(l), MapKey::Number(r)) => l.total_cmp(r),
+            // an int and a float key are never the same, break ties between
+            // values that round to the same float by putting the int first
+            (MapKey::Int(l), MapKey::Number(r)) => (*l as f64).total_cmp(r).then(Ordering::Less),
+            (MapKey::Number(l), MapKey::Int(r)) => {
+                l.total_cmp(&(*r as f64)).then(Ordering::Greater)
+            }
+            (MapKey::Str(l), MapKey::Str(r)) => l.cmp(r),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl Hash for MapKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.rank().hash(state);
+        match self {
+            MapKey::Nil => {}
+            MapKey::Bool(b) => b.hash(state),
+            MapKey::Int(n) => n.hash(state),
+            MapKey::Number(n) => n.to_bits().hash(state),
+            MapKey::Str(s) => s.hash(state),
+        }
+    }
+}
+
+impl fmt::Display for MapKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_value())
     }
 }
 
 #[derive(Debug, Clone)]
 pub enum EvalValue {
-    Number(f32),
+    Number(f64),
+    Int(i64),
     Str(Rc<String>),
     Bool(bool),
     Function(Rc<LoxFunction>),
+    NativeFunction(Rc<NativeFunction>),
+    Class(Rc<LoxClass>),
+    Instance(Rc<LoxInstance>),
+    List(Rc<RefCell<Vec<EvalValue>>>),
+    Map(Rc<RefCell<BTreeMap<MapKey, EvalValue>>>),
     Nil,
 }
 
+// conversions for passing Rust values into lox
+impl From<f64> for EvalValue {
+    fn from(n: f64) -> EvalValue {
+        EvalValue::Number(n)
+    }
+}
+
+impl From<i64> for EvalValue {
+    fn from(n: i64) -> EvalValue {
+        EvalValue::Int(n)
+    }
+}
+
+impl From<bool> for EvalValue {
+    fn from(b: bool) -> EvalValue {
+        EvalValue::Bool(b)
+    }
+}
+
+impl From<&str> for EvalValue {
+    fn from(s: &str) -> EvalValue {
+        EvalValue::Str(Rc::new(s.to_string()))
+    }
+}
+
+impl From<String> for EvalValue {
+    fn from(s: String) -> EvalValue {
+        EvalValue::Str(Rc::new(s))
+    }
+}
+
 impl fmt::Display for EvalValue {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, &mut vec![])
+    }
+}
+
+impl EvalValue {
+    // 'open' holds the lists and maps being written further out, so a
+    // collection that contains itself is written as [...] or {...}
+    fn write(&self, f: &mut fmt::Formatter<'_>, open: &mut Vec<*const ()>) -> fmt::Result {
         match self {
-            EvalValue::Number(n) => write!(f, "{}", n),
+            EvalValue::Number(n) => write_number(f, *n),
+            EvalValue::Int(n) => write!(f, "{}", n),
             EvalValue::Str(s) => write!(f, "{}", s),
             EvalValue::Bool(b) => write!(f, "{}", if *b { "true" } else { "false" }),
+            EvalValue::Function(func) if func.declaration.is_anonymous() => {
+                write!(f, "Lox function <anonymous>")
+            }
             EvalValue::Function(func) => write!(f, "Lox function <{}>", func.declaration.name),
+            EvalValue::NativeFunction(func) => write!(f, "Native function <{}>", func.name),
+            EvalValue::Class(class) => write!(f, "Lox class <{}>", class.name),
+            EvalValue::Instance(instance) => write!(f, "Lox instance <{}>", instance.class.name),
+            EvalValue::List(list) => {
+                let id = Rc::as_ptr(list) as *const ();
+                if open.contains(&id) {
+                    return write!(f, "[...]");
+                }
+
+                open.push(id);
+                write!(f, "[")?;
+                for (i, element) in list.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    element.write_element(f, open)?;
+                }
+                open.pop();
+                write!(f, "]")
+            }
+            EvalValue::Map(map) => {
+                let id = Rc::as_ptr(map) as *const ();
+                if open.contains(&id) {
+                    return write!(f, "{{...}}");
+                }
+
+                open.push(id);
+                write!(f, "{{")?;
+                for (i, (key, value)) in map.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    key.to_value().write_element(f, open)?;
+                    write!(f, ": ")?;
+                    value.write_element(f, open)?;
+                }
+                open.pop();
+                write!(f, "}}")
+            }
             EvalValue::Nil => write!(f, "nil"),
         }
     }
+
+    // Strings inside a list or map are quoted, so ["1", 1] can't be taken for [1, 1]
+    fn write_element(&self, f: &mut fmt::Formatter<'_>, open: &mut Vec<*const ()>) -> fmt::Result {
+        match self {
+            EvalValue::Str(s) => write!(f, "{:?}", s),
+            _ => self.write(f, open),
+        }
+    }
+}
+
+// Magnitudes outside [1e-6, 1e21) are written with an exponent, as 1e300
+// rather than 1 followed by 300 zeros
+fn write_number(f: &mut fmt::Formatter<'_>, n: f64) -> fmt::Result {
+    let magnitude = n.abs();
+    if magnitude != 0.0 && magnitude.is_finite() && !(1e-6..1e21).contains(&magnitude) {
+        return write!(f, "{:e}", n);
+    }
+
+    return write!(f, "{}", n);
 }
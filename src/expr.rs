@@ -1,4 +1,8 @@
-use crate::token;
+use crate::stmt;
+use crate::symbol::{self, Symbol};
+use crate::token::{self, Span};
+use std::cell::Cell;
+use std::rc::Rc;
 
 #[derive(Debug, PartialEq)]
 pub struct Binary {
@@ -7,112 +11,333 @@ pub struct Binary {
     pub right: Box<Expr>,
 }
 
+// 'and' and 'or', kept apart from Binary since the right operand is only
+// evaluated when the left one doesn't decide the result
+#[derive(Debug, PartialEq)]
+pub struct Logical {
+    pub left: Box<Expr>,
+    pub operator: token::Token,
+    pub right: Box<Expr>,
+}
+
+// 'depth' is filled in by the resolver with the number of scopes between
+// the use and the declaration, it stays None for globals
 #[derive(Debug, PartialEq)]
 pub struct Variable {
-    pub name: String,
+    pub name: Symbol,
+    pub line: u32,
+    pub depth: Cell<Option<usize>>,
+}
+
+impl Variable {
+    pub fn new(name: &str, line: u32) -> Variable {
+        Variable {
+            name: Symbol::intern(name),
+            line,
+            depth: Cell::new(None),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct UnaryNegate {
+    pub expr: Box<Expr>,
     pub line: u32,
 }
 
 #[derive(Debug, PartialEq)]
 pub struct Assignment {
-    pub target: String,
+    pub target: Symbol,
     pub line: u32,
     pub expr: Box<Expr>,
+    pub depth: Cell<Option<usize>>,
 }
 
 #[derive(Debug, PartialEq)]
 pub struct Call {
     pub callee: Box<Expr>,
     pub line: u32,
-    pub arguments: Vec<Box<Expr>>,
+    pub arguments: Vec<Expr>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Get {
+    pub object: Box<Expr>,
+    pub name: String,
+    pub line: u32,
 }
 
 #[derive(Debug, PartialEq)]
-pub enum Expr {
+pub struct Set {
+    pub object: Box<Expr>,
+    pub name: String,
+    pub line: u32,
+    pub value: Box<Expr>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct This {
+    pub line: u32,
+    pub depth: Cell<Option<usize>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Super {
+    pub method: String,
+    pub line: u32,
+    pub depth: Cell<Option<usize>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Index {
+    pub object: Box<Expr>,
+    pub index: Box<Expr>,
+    pub line: u32,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct IndexSet {
+    pub object: Box<Expr>,
+    pub index: Box<Expr>,
+    pub line: u32,
+    pub value: Box<Expr>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Map {
+    pub entries: Vec<(Expr, Expr)>,
+    pub line: u32,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ExprKind {
     Bool(bool),
     Str(String),
-    Number(f32),
+    Number(f64),
+    Int(i64),
     Binary(Binary),
+    Logical(Logical),
     Grouping(Box<Expr>),
     LogicalNot(Box<Expr>),
-    UnaryNegate(Box<Expr>),
+    UnaryNegate(UnaryNegate),
     Variable(Variable),
     Assignment(Assignment),
     Call(Call),
+    Get(Get),
+    Set(Set),
+    This(This),
+    Super(Super),
+    Lambda(Rc<stmt::Function>),
+    List(Vec<Expr>),
+    Map(Map),
+    Index(Index),
+    IndexSet(IndexSet),
     Nil,
 }
 
+// 'span' covers the source text of the whole expression, it's left
+// empty for nodes that weren't parsed from source
+#[derive(Debug, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
 pub trait ExprVisitor<T> {
     fn visit_literal_bool(&self, literal_bool: &bool) -> T;
     fn visit_literal_str(&self, literal_str: &str) -> T;
-    fn visit_literal_number(&self, literal_number: &f32) -> T;
+    fn visit_literal_number(&self, literal_number: &f64) -> T;
+    fn visit_literal_int(&self, literal_int: &i64) -> T;
     fn visit_binary(&mut self, binary: &Binary) -> T;
+    fn visit_logical(&mut self, logical: &Logical) -> T;
     fn visit_grouping(&mut self, grouping: &Expr) -> T;
     fn visit_logical_not(&mut self, expr: &Expr) -> T;
-    fn visit_unary_negate(&mut self, expr: &Expr) -> T;
+    fn visit_unary_negate(&mut self, negate: &UnaryNegate) -> T;
     fn visit_variable(&mut self, variable: &Variable) -> T;
     fn visit_assignment(&mut self, assignment: &Assignment) -> T;
     fn visit_call(&mut self, call: &Call) -> T;
+    fn visit_get(&mut self, get: &Get) -> T;
+    fn visit_set(&mut self, set: &Set) -> T;
+    fn visit_this(&mut self, this: &This) -> T;
+    fn visit_super(&mut self, sup: &Super) -> T;
+    fn visit_lambda(&mut self, function: &Rc<stmt::Function>) -> T;
+    fn visit_list(&mut self, elements: &[Expr]) -> T;
+    fn visit_map(&mut self, map: &Map) -> T;
+    fn visit_index(&mut self, index: &Index) -> T;
+    fn visit_index_set(&mut self, index_set: &IndexSet) -> T;
     fn visit_nil(&self) -> T;
 }
 
 impl Expr {
+    pub fn new(kind: ExprKind) -> Expr {
+        Expr {
+            kind,
+            span: Span::default(),
+        }
+    }
+
     pub fn accept<T>(&self, visitor: &mut impl ExprVisitor<T>) -> T {
-        match self {
-            Expr::Bool(b) => visitor.visit_literal_bool(b),
-            Expr::Str(s) => visitor.visit_literal_str(s),
-            Expr::Number(n) => visitor.visit_literal_number(n),
-            Expr::Binary(b) => visitor.visit_binary(b),
-            Expr::Grouping(g) => visitor.visit_grouping(g),
-            Expr::LogicalNot(ln) => visitor.visit_logical_not(ln),
-            Expr::UnaryNegate(un) => visitor.visit_unary_negate(un),
-            Expr::Variable(v) => visitor.visit_variable(&v),
-            Expr::Assignment(v) => visitor.visit_assignment(&v),
-            Expr::Call(v) => visitor.visit_call(&v),
-            Expr::Nil => visitor.visit_nil(),
+        match &self.kind {
+            ExprKind::Bool(b) => visitor.visit_literal_bool(b),
+            ExprKind::Str(s) => visitor.visit_literal_str(s),
+            ExprKind::Number(n) => visitor.visit_literal_number(n),
+            ExprKind::Int(n) => visitor.visit_literal_int(n),
+            ExprKind::Binary(b) => visitor.visit_binary(b),
+            ExprKind::Logical(l) => visitor.visit_logical(l),
+            ExprKind::Grouping(g) => visitor.visit_grouping(g),
+            ExprKind::LogicalNot(ln) => visitor.visit_logical_not(ln),
+            ExprKind::UnaryNegate(un) => visitor.visit_unary_negate(un),
+            ExprKind::Variable(v) => visitor.visit_variable(v),
+            ExprKind::Assignment(v) => visitor.visit_assignment(v),
+            ExprKind::Call(v) => visitor.visit_call(v),
+            ExprKind::Get(v) => visitor.visit_get(v),
+            ExprKind::Set(v) => visitor.visit_set(v),
+            ExprKind::This(v) => visitor.visit_this(v),
+            ExprKind::Super(v) => visitor.visit_super(v),
+            ExprKind::Lambda(v) => visitor.visit_lambda(v),
+            ExprKind::List(v) => visitor.visit_list(v),
+            ExprKind::Map(v) => visitor.visit_map(v),
+            ExprKind::Index(v) => visitor.visit_index(v),
+            ExprKind::IndexSet(v) => visitor.visit_index_set(v),
+            ExprKind::Nil => visitor.visit_nil(),
         }
     }
 }
 
 pub fn new_binary(left: Expr, operator: token::Token, right: Expr) -> Expr {
-    Expr::Binary(Binary {
+    Expr::new(ExprKind::Binary(Binary {
         left: Box::new(left),
         operator,
         right: Box::new(right),
-    })
+    }))
+}
+
+pub fn new_logical(left: Expr, operator: token::Token, right: Expr) -> Expr {
+    Expr::new(ExprKind::Logical(Logical {
+        left: Box::new(left),
+        operator,
+        right: Box::new(right),
+    }))
 }
 
 pub fn new_grouping(expr: Expr) -> Expr {
-    Expr::Grouping(Box::new(expr))
+    Expr::new(ExprKind::Grouping(Box::new(expr)))
 }
 
 pub fn new_logical_not(expr: Expr) -> Expr {
-    Expr::LogicalNot(Box::new(expr))
+    Expr::new(ExprKind::LogicalNot(Box::new(expr)))
 }
 
-pub fn new_unary_negate(expr: Expr) -> Expr {
-    Expr::UnaryNegate(Box::new(expr))
+pub fn new_unary_negate(expr: Expr, line: u32) -> Expr {
+    Expr::new(ExprKind::UnaryNegate(UnaryNegate {
+        expr: Box::new(expr),
+        line,
+    }))
 }
 
 pub fn new_variable(name: &str, line: u32) -> Expr {
-    Expr::Variable(Variable {
-        name: name.to_string(),
-        line,
-    })
+    Expr::new(ExprKind::Variable(Variable::new(name, line)))
 }
 
 pub fn new_assignment(target: &str, line: u32, expr: Expr) -> Expr {
-    Expr::Assignment(Assignment {
-        target: target.to_string(),
+    Expr::new(ExprKind::Assignment(Assignment {
+        target: Symbol::intern(target),
         line,
         expr: Box::new(expr),
-    })
+        depth: Cell::new(None),
+    }))
 }
 
-pub fn new_call(callee: Expr, line: u32, arguments: Vec<Box<Expr>>) -> Expr {
-    Expr::Call(Call {
+pub fn new_call(callee: Expr, line: u32, arguments: Vec<Expr>) -> Expr {
+    Expr::new(ExprKind::Call(Call {
         callee: Box::new(callee),
         line,
         arguments,
-    })
+    }))
+}
+
+pub fn new_get(object: Expr, name: &str, line: u32) -> Expr {
+    Expr::new(ExprKind::Get(Get {
+        object: Box::new(object),
+        name: name.to_string(),
+        line,
+    }))
+}
+
+pub fn new_set(object: Expr, name: &str, line: u32, value: Expr) -> Expr {
+    Expr::new(ExprKind::Set(Set {
+        object: Box::new(object),
+        name: name.to_string(),
+        line,
+        value: Box::new(value),
+    }))
+}
+
+pub fn new_this(line: u32) -> Expr {
+    Expr::new(ExprKind::This(This {
+        line,
+        depth: Cell::new(None),
+    }))
+}
+
+pub fn new_super(method: &str, line: u32) -> Expr {
+    Expr::new(ExprKind::Super(Super {
+        method: method.to_string(),
+        line,
+        depth: Cell::new(None),
+    }))
+}
+
+pub fn new_lambda(parameters: Vec<Symbol>, statements: Vec<stmt::Stmt>, line: u32) -> Expr {
+    Expr::new(ExprKind::Lambda(Rc::new(stmt::Function {
+        name: symbol::EMPTY,
+        parameters,
+        statements,
+        line,
+    })))
+}
+
+pub fn new_index(object: Expr, index: Expr, line: u32) -> Expr {
+    Expr::new(ExprKind::Index(Index {
+        object: Box::new(object),
+        index: Box::new(index),
+        line,
+    }))
+}
+
+pub fn new_index_set(object: Expr, index: Expr, line: u32, value: Expr) -> Expr {
+    Expr::new(ExprKind::IndexSet(IndexSet {
+        object: Box::new(object),
+        index: Box::new(index),
+        line,
+        value: Box::new(value),
+    }))
+}
+
+pub fn new_map(entries: Vec<(Expr, Expr)>, line: u32) -> Expr {
+    Expr::new(ExprKind::Map(Map { entries, line }))
+}
+
+pub fn new_bool(value: bool) -> Expr {
+    Expr::new(ExprKind::Bool(value))
+}
+
+pub fn new_str(value: &str) -> Expr {
+    Expr::new(ExprKind::Str(value.to_string()))
+}
+
+pub fn new_number(value: f64) -> Expr {
+    Expr::new(ExprKind::Number(value))
+}
+
+pub fn new_int(value: i64) -> Expr {
+    Expr::new(ExprKind::Int(value))
+}
+
+pub fn new_list(elements: Vec<Expr>) -> Expr {
+    Expr::new(ExprKind::List(elements))
+}
+
+pub fn new_nil() -> Expr {
+    Expr::new(ExprKind::Nil)
 }
@@ -1,53 +1,196 @@
 use crate::environment::Environment;
+use crate::error::{new_runtime_error, ErrorDetail, ErrorKind, Frame, LoxError};
 use crate::eval_value;
-use crate::eval_value::EvalValue;
+use crate::eval_value::{EvalValue, MapKey};
 use crate::expr;
+use crate::stack;
 use crate::stmt;
+use crate::symbol::{self, Symbol};
 use crate::token::TokenType;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
 use std::rc::Rc;
 
 pub struct InterpreterContext<'a> {
     pub global_environment: &'a mut Environment,
-    pub local_environment: Option<Environment>,
+    pub local_environment: Option<Rc<Environment>>,
+    call_stack: Vec<CallFrame>,
+    // where 'print' writes to
+    output: &'a mut dyn Write,
 }
 
-type StmtResult = Result<Option<EvalValue>, String>;
-type EvalResult = Result<EvalValue, String>;
-impl<'a> InterpreterContext<'a> {
-    pub fn new(global_environment: &'a mut Environment) -> InterpreterContext<'a> {
-        InterpreterContext {
-            global_environment,
-            local_environment: None,
+// How deep lox calls can nest before the script is stopped
+const MAX_CALL_DEPTH: usize = 1000;
+
+// Stack kept free for reporting errors. Calls stop with more of it left than
+// statements and expressions do, so runaway recursion is caught at a call,
+// which has a line for the traceback
+const CALL_STACK_RESERVE: usize = 256 * 1024;
+const NESTING_STACK_RESERVE: usize = 128 * 1024;
+
+// A call to a lox function that hasn't returned yet
+struct CallFrame {
+    function: Symbol,
+    // where the function was called from, None when called from Rust
+    line: Option<u32>,
+}
+
+// How a statement finished when it didn't simply fall through to the next one
+#[derive(Debug)]
+pub enum Completion {
+    Return(EvalValue),
+    Break,
+    Continue,
+}
+
+type StmtResult = Result<Option<Completion>, LoxError>;
+type EvalResult = Result<EvalValue, LoxError>;
+
+// The operands of an arithmetic operator, ints stay ints only when both are
+// ints, otherwise both are promoted to floats
+enum Numbers {
+    Ints(i64, i64),
+    Floats(f64, f64),
+}
+
+impl Numbers {
+    fn from_values(left: &EvalValue, right: &EvalValue) -> Option<Numbers> {
+        match (left, right) {
+            (EvalValue::Int(l), EvalValue::Int(r)) => Some(Numbers::Ints(*l, *r)),
+            (EvalValue::Int(l), EvalValue::Number(r)) => Some(Numbers::Floats(*l as f64, *r)),
+            (EvalValue::Number(l), EvalValue::Int(r)) => Some(Numbers::Floats(*l, *r as f64)),
+            (EvalValue::Number(l), EvalValue::Number(r)) => Some(Numbers::Floats(*l, *r)),
+            _ => None,
         }
     }
+}
 
-    pub fn new_with_local_env(
+// '/' always gives a float, '~/' and '%' round towards negative infinity so
+// that the remainder takes the sign of the divisor, as in Python. Only ints
+// raise an error on division by zero, floats give inf or NaN
+fn arithmetic(operator: &TokenType, numbers: Numbers, line: u32) -> EvalResult {
+    let is_division = matches!(
+        operator,
+        TokenType::Slash | TokenType::TildeSlash | TokenType::Percent
+    );
+
+    match numbers {
+        Numbers::Ints(_, 0) if is_division => Err(new_runtime_error(
+            ErrorKind::DivisionByZero,
+            "Division by zero".to_string(),
+            line,
+        )),
+        Numbers::Ints(l, r) => {
+            let result = match operator {
+                TokenType::Plus => l.checked_add(r),
+                TokenType::Minus => l.checked_sub(r),
+                TokenType::Star => l.checked_mul(r),
+                TokenType::Slash => return Ok(EvalValue::Number(l as f64 / r as f64)),
+                TokenType::TildeSlash => l.checked_div(r).map(|q| {
+                    if l % r != 0 && (l < 0) != (r < 0) {
+                        q - 1
+                    } else {
+                        q
+                    }
+                }),
+                TokenType::Percent => {
+                    // can't overflow, i64::MIN % -1 is 0
+                    let m = l.wrapping_rem(r);
+                    Some(if m != 0 && (m < 0) != (r < 0) {
+                        m + r
+                    } else {
+                        m
+                    })
+                }
+                _ => panic!("Unexpected arithmetic operator: {:?}", operator),
+            };
+
+            result.map(EvalValue::Int).ok_or_else(|| {
+                new_runtime_error(
+                    ErrorKind::IntegerOverflow,
+                    "Integer overflow".to_string(),
+                    line,
+                )
+            })
+        }
+        Numbers::Floats(l, r) => {
+            let result = match operator {
+                TokenType::Plus => l + r,
+                TokenType::Minus => l - r,
+                TokenType::Star => l * r,
+                TokenType::Slash => l / r,
+                TokenType::TildeSlash => (l / r).floor(),
+                TokenType::Percent => {
+                    let m = l % r;
+                    if m != 0.0 && (m < 0.0) != (r < 0.0) {
+                        m + r
+                    } else {
+                        m
+                    }
+                }
+                _ => panic!("Unexpected arithmetic operator: {:?}", operator),
+            };
+
+            Ok(EvalValue::Number(result))
+        }
+    }
+}
+impl<'a> InterpreterContext<'a> {
+    pub fn new(
         global_environment: &'a mut Environment,
-        local_environment: Environment,
+        output: &'a mut dyn Write,
     ) -> InterpreterContext<'a> {
         InterpreterContext {
             global_environment,
-            local_environment: Some(local_environment),
+            local_environment: None,
+            call_stack: vec![],
+            output,
         }
     }
+
+    // only nil and false are falsey
     fn is_truthy(&self, eval_value: &EvalValue) -> bool {
-        let truthy_value = match eval_value {
-            EvalValue::Number(n) => *n != 0.0,
-            EvalValue::Str(s) => !s.is_empty(),
+        match eval_value {
             EvalValue::Bool(b) => *b,
-            EvalValue::Function(_) => true,
             EvalValue::Nil => false,
-        };
+            _ => true,
+        }
+    }
 
-        truthy_value
+    // Values of different types are never equal, functions, classes,
+    // instances, lists and maps are equal only to themselves
+    fn is_equal(&self, left: &EvalValue, right: &EvalValue) -> bool {
+        match (left, right) {
+            (EvalValue::Nil, EvalValue::Nil) => true,
+            (EvalValue::Bool(l), EvalValue::Bool(r)) => l == r,
+            (EvalValue::Number(l), EvalValue::Number(r)) => l == r,
+            (EvalValue::Int(l), EvalValue::Int(r)) => l == r,
+            (EvalValue::Int(l), EvalValue::Number(r))
+            | (EvalValue::Number(r), EvalValue::Int(l)) => *l as f64 == *r,
+            (EvalValue::Str(l), EvalValue::Str(r)) => l == r,
+            (EvalValue::Function(l), EvalValue::Function(r)) => Rc::ptr_eq(l, r),
+            (EvalValue::NativeFunction(l), EvalValue::NativeFunction(r)) => Rc::ptr_eq(l, r),
+            (EvalValue::Class(l), EvalValue::Class(r)) => Rc::ptr_eq(l, r),
+            (EvalValue::Instance(l), EvalValue::Instance(r)) => Rc::ptr_eq(l, r),
+            (EvalValue::List(l), EvalValue::List(r)) => Rc::ptr_eq(l, r),
+            (EvalValue::Map(l), EvalValue::Map(r)) => Rc::ptr_eq(l, r),
+            _ => false,
+        }
     }
 
     pub fn interpret(&mut self, stmts: &[stmt::Stmt]) -> StmtResult {
-        self.execute_many(&stmts)
+        self.execute_many(stmts)
     }
 
     pub fn execute(&mut self, stmt: &stmt::Stmt) -> StmtResult {
+        if stack::remaining_below(NESTING_STACK_RESERVE) {
+            return Err(stack_overflow(None).with_default_span(stmt.span));
+        }
+
         stmt.accept(self)
+            .map_err(|e| e.with_default_span(stmt.span))
     }
 
     pub fn execute_many(&mut self, stmts: &[stmt::Stmt]) -> StmtResult {
@@ -61,25 +204,214 @@ impl<'a> InterpreterContext<'a> {
     }
 
     pub fn evaluate_expr(&mut self, expr: &expr::Expr) -> EvalResult {
-        return expr.accept(self);
+        if stack::remaining_below(NESTING_STACK_RESERVE) {
+            return Err(stack_overflow(None).with_default_span(expr.span));
+        }
+
+        return expr
+            .accept(self)
+            .map_err(|e| e.with_default_span(expr.span));
+    }
+
+    fn define(&mut self, name: Symbol, value: EvalValue) {
+        if let Some(local_environment) = &self.local_environment {
+            local_environment.define_var(name, value);
+        } else {
+            self.global_environment.define_var(name, value);
+        }
+    }
+
+    fn list_index(&self, index: &EvalValue, len: usize, line: u32) -> Result<usize, LoxError> {
+        let index = match index {
+            EvalValue::Int(n) if *n >= 0 => *n as usize,
+            EvalValue::Number(n) if *n >= 0.0 && n.fract() == 0.0 => *n as usize,
+            _ => {
+                return Err(new_runtime_error(
+                    ErrorKind::InvalidIndex,
+                    "List index must be a non-negative integer".to_string(),
+                    line,
+                ))
+            }
+        };
+
+        if index >= len {
+            return Err(new_runtime_error(
+                ErrorKind::IndexOutOfRange,
+                format!("List index {} out of range for length {}", index, len),
+                line,
+            ));
+        }
+
+        Ok(index)
+    }
+
+    fn map_key(&self, key: &EvalValue, line: u32) -> Result<MapKey, LoxError> {
+        match MapKey::from_value(key) {
+            Some(key) => Ok(key),
+            None => Err(new_runtime_error(
+                ErrorKind::InvalidIndex,
+                "Map keys must be strings, numbers, bools or nil".to_string(),
+                line,
+            )),
+        }
+    }
+
+    // the resolver leaves globals without a depth, so they skip the local scopes
+    fn look_up_variable(&self, name: Symbol, depth: Option<usize>, line: u32) -> EvalResult {
+        let value = match (depth, &self.local_environment) {
+            (Some(depth), Some(local_environment)) => local_environment.get_at(depth, name),
+            _ => self.global_environment.get_var(name),
+        };
+
+        match value {
+            Some(v) => Ok(v),
+            None => Err(new_runtime_error(
+                ErrorKind::UndefinedVariable,
+                format!("Undefined variable {}", name),
+                line,
+            )),
+        }
+    }
+
+    // Calls a function, native function or class with arguments that were
+    // already evaluated. 'line' is where the call is made from
+    pub fn call_value(
+        &mut self,
+        callee: EvalValue,
+        arguments: &[EvalValue],
+        line: Option<u32>,
+    ) -> EvalResult {
+        let error = |kind, message| {
+            LoxError::Runtime(Box::new(ErrorDetail {
+                line,
+                ..ErrorDetail::new(kind, message)
+            }))
+        };
+
+        let arity = match &callee {
+            EvalValue::Function(f) => f.declaration.arity(),
+            EvalValue::NativeFunction(f) => f.arity,
+            EvalValue::Class(c) => c.arity(),
+            _ => {
+                return Err(error(
+                    ErrorKind::NotCallable,
+                    "Not a callable object".to_string(),
+                ))
+            }
+        };
+
+        if arity != arguments.len() as u32 {
+            return Err(error(
+                ErrorKind::ArityMismatch,
+                format!("Expected {} arguments but got {}", arity, arguments.len()),
+            ));
+        }
+
+        match callee {
+            EvalValue::Function(f) => {
+                return self.call_function(f, arguments, line);
+            }
+            EvalValue::NativeFunction(f) => {
+                return f
+                    .call(arguments)
+                    .map_err(|e| error(ErrorKind::NativeError, e));
+            }
+            EvalValue::Class(c) => {
+                let instance = Rc::new(eval_value::LoxInstance::new(c.clone()));
+                if let Some(initializer) = c.find_method("init") {
+                    let initializer = Rc::new(initializer.bind(instance.clone()));
+                    self.call_function(initializer, arguments, line)?;
+                }
+
+                return Ok(EvalValue::Instance(instance));
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn call_function(
+        &mut self,
+        function: Rc<eval_value::LoxFunction>,
+        arguments: &[EvalValue],
+        line: Option<u32>,
+    ) -> EvalResult {
+        if self.call_stack.len() >= MAX_CALL_DEPTH || stack::remaining_below(CALL_STACK_RESERVE) {
+            return Err(self.traced(stack_overflow(line)));
+        }
+
+        self.call_stack.push(CallFrame {
+            function: function.declaration.name,
+            line,
+        });
+        let result =
+            eval_value::LoxFunction::call(function, self, arguments).map_err(|e| self.traced(e));
+        self.call_stack.pop();
+
+        result
+    }
+
+    // Records the calls an error is unwinding through. This runs first in the
+    // innermost call, while the whole stack is still there
+    fn traced(&self, mut error: LoxError) -> LoxError {
+        let detail = error.detail_mut();
+        if !detail.trace.is_empty() {
+            return error;
+        }
+
+        let mut line = detail.line;
+        for frame in self.call_stack.iter().rev() {
+            let function = if frame.function.is_empty() {
+                "<anonymous>".to_string()
+            } else {
+                frame.function.to_string()
+            };
+            detail.trace.push(Frame { function, line });
+            line = frame.line;
+        }
+
+        // the outermost call came from the script rather than from Rust
+        if line.is_some() {
+            detail.trace.push(Frame {
+                function: "script".to_string(),
+                line,
+            });
+        }
+
+        error
     }
 }
 
+fn stack_overflow(line: Option<u32>) -> LoxError {
+    LoxError::Runtime(Box::new(ErrorDetail {
+        line,
+        ..ErrorDetail::new(ErrorKind::StackOverflow, "Stack overflow".to_string())
+    }))
+}
+
 impl stmt::StmtVisitor<StmtResult> for InterpreterContext<'_> {
     fn visit_expr(&mut self, expr: &expr::Expr) -> StmtResult {
         //println!("{:#?}", self.evaluate_expr(&expr));
-        self.evaluate_expr(&expr)?;
+        self.evaluate_expr(expr)?;
         Ok(None)
     }
 
     fn visit_print(&mut self, print: &stmt::Print) -> StmtResult {
+        let mut line = String::new();
         for expr in &print.exprs {
-            match self.evaluate_expr(&expr) {
-                Ok(value) => print!("{} ", value),
+            match self.evaluate_expr(expr) {
+                Ok(value) => line += &format!("{} ", value),
                 Err(e) => return Err(e),
             }
         }
-        println!("");
+
+        writeln!(self.output, "{}", line)
+            .and_then(|_| self.output.flush())
+            .map_err(|e| {
+                LoxError::Runtime(Box::new(ErrorDetail::new(
+                    ErrorKind::Io,
+                    format!("Failed to write output: {}", e),
+                )))
+            })?;
         Ok(None)
     }
 
@@ -93,7 +425,7 @@ impl stmt::StmtVisitor<StmtResult> for InterpreterContext<'_> {
                 return Ok(result);
             }
         } else if let Some(branch) = &if_ctx.else_branch {
-            let result = self.execute(&branch)?;
+            let result = self.execute(branch)?;
             if result.is_some() {
                 return Ok(result);
             }
@@ -103,33 +435,21 @@ impl stmt::StmtVisitor<StmtResult> for InterpreterContext<'_> {
     }
 
     fn visit_block(&mut self, block: &stmt::Block) -> StmtResult {
-        if self.local_environment.is_some() {
-            self.local_environment.as_mut().unwrap().push_scope();
-            let result = self.execute_many(&block.statements);
-            self.local_environment.as_mut().unwrap().pop_scope();
+        let environment = Environment::new_enclosed(self.local_environment.clone());
+        let enclosing = self.local_environment.replace(Rc::new(environment));
+        let result = self.execute_many(&block.statements);
+        self.local_environment = enclosing;
 
-            return result;
-        } else {
-            let local_env = Environment::new();
-            let mut local_scope =
-                InterpreterContext::new_with_local_env(self.global_environment, local_env);
-
-            return local_scope.execute_many(&block.statements);
-        }
+        return result;
     }
 
     fn visit_var(&mut self, var: &stmt::Var) -> StmtResult {
         let initializer = match &var.initializer {
             Some(initializer) => self.evaluate_expr(initializer)?,
-            None => EvalValue::Nil
+            None => EvalValue::Nil,
         };
 
-        if let Some(local_environment) = &mut self.local_environment {
-            local_environment.define_var(&var.name, initializer.clone());
-        } else {
-            self.global_environment
-                .define_var(&var.name, initializer.clone());
-        }
+        self.define(var.name, initializer);
         Ok(None)
     }
 
@@ -140,9 +460,14 @@ impl stmt::StmtVisitor<StmtResult> for InterpreterContext<'_> {
                 break;
             }
 
-            let result = self.execute(&while_ctx.body)?;
-            if result.is_some() {
-                return Ok(result);
+            match self.execute(&while_ctx.body)? {
+                Some(Completion::Break) => break,
+                Some(Completion::Return(value)) => return Ok(Some(Completion::Return(value))),
+                Some(Completion::Continue) | None => {}
+            }
+
+            if let Some(increment) = &while_ctx.increment {
+                self.evaluate_expr(increment)?;
             }
         }
         Ok(None)
@@ -152,25 +477,86 @@ impl stmt::StmtVisitor<StmtResult> for InterpreterContext<'_> {
         let lox_function = eval_value::LoxFunction {
             declaration: function.clone(),
             closure: self.local_environment.clone(),
+            is_initializer: false,
         };
 
-        if let Some(local_environment) = &mut self.local_environment {
-            local_environment.set_var(
-                &function.name,
-                eval_value::EvalValue::Function(Rc::new(lox_function)),
-            );
-        } else {
-            self.global_environment.set_var(
-                &function.name,
-                eval_value::EvalValue::Function(Rc::new(lox_function)),
-            );
-        }
+        self.define(function.name, EvalValue::Function(Rc::new(lox_function)));
         return Ok(None);
     }
 
-    fn visit_return(&mut self, expr: &expr::Expr) -> StmtResult {
-        let value = self.evaluate_expr(expr)?;
-        return Ok(Some(value));
+    fn visit_return(&mut self, return_ctx: &stmt::Return) -> StmtResult {
+        let value = match &return_ctx.value {
+            Some(expr) => self.evaluate_expr(expr)?,
+            None => EvalValue::Nil,
+        };
+        return Ok(Some(Completion::Return(value)));
+    }
+
+    fn visit_break(&mut self) -> StmtResult {
+        Ok(Some(Completion::Break))
+    }
+
+    fn visit_continue(&mut self) -> StmtResult {
+        Ok(Some(Completion::Continue))
+    }
+
+    fn visit_class(&mut self, class: &stmt::Class) -> StmtResult {
+        let superclass = match &class.superclass {
+            None => None,
+            Some(superclass) => {
+                if superclass.name == class.name {
+                    return Err(new_runtime_error(
+                        ErrorKind::InvalidInheritance,
+                        "A class can't inherit from itself".to_string(),
+                        superclass.line,
+                    ));
+                }
+
+                match self.look_up_variable(
+                    superclass.name,
+                    superclass.depth.get(),
+                    superclass.line,
+                )? {
+                    EvalValue::Class(c) => Some(c),
+                    _ => {
+                        return Err(new_runtime_error(
+                            ErrorKind::InvalidInheritance,
+                            "Superclass must be a class".to_string(),
+                            superclass.line,
+                        ))
+                    }
+                }
+            }
+        };
+
+        // methods of a subclass close over an extra scope holding 'super'
+        let closure = match &superclass {
+            None => self.local_environment.clone(),
+            Some(superclass) => {
+                let environment = Environment::new_enclosed(self.local_environment.clone());
+                environment.define_var(symbol::SUPER, EvalValue::Class(superclass.clone()));
+                Some(Rc::new(environment))
+            }
+        };
+
+        let mut methods = HashMap::new();
+        for method in &class.methods {
+            let lox_function = eval_value::LoxFunction {
+                declaration: method.clone(),
+                closure: closure.clone(),
+                is_initializer: method.name == symbol::INIT,
+            };
+            methods.insert(method.name.to_string(), Rc::new(lox_function));
+        }
+
+        let lox_class = eval_value::LoxClass {
+            name: class.name.to_string(),
+            superclass,
+            methods,
+        };
+
+        self.define(class.name, EvalValue::Class(Rc::new(lox_class)));
+        Ok(None)
     }
 }
 
@@ -183,90 +569,96 @@ impl expr::ExprVisitor<EvalResult> for InterpreterContext<'_> {
         return Ok(EvalValue::Str(Rc::new(literal_str.to_string())));
     }
 
-    fn visit_literal_number(&self, literal_number: &f32) -> EvalResult {
+    fn visit_literal_number(&self, literal_number: &f64) -> EvalResult {
         return Ok(EvalValue::Number(*literal_number));
     }
 
+    fn visit_literal_int(&self, literal_int: &i64) -> EvalResult {
+        return Ok(EvalValue::Int(*literal_int));
+    }
+
     fn visit_binary(&mut self, binary: &expr::Binary) -> EvalResult {
         let left = self.evaluate_expr(&binary.left)?;
         let right = self.evaluate_expr(&binary.right)?;
 
-        let get_numbers = || -> Result<(f32, f32), String> {
-            match (&left, &right) {
-                (EvalValue::Number(l), EvalValue::Number(r)) => Ok((*l, *r)),
-                _ => Err(format!("Must be numbers at line {}", binary.operator.line)),
-            }
+        let get_numbers = || -> Result<Numbers, LoxError> {
+            Numbers::from_values(&left, &right).ok_or_else(|| {
+                new_runtime_error(
+                    ErrorKind::TypeMismatch,
+                    "Must be numbers".to_string(),
+                    binary.operator.line,
+                )
+            })
         };
 
         match binary.operator.token_type {
-            TokenType::Less => {
-                let (l, r) = get_numbers()?;
-                Ok(EvalValue::Bool(l < r))
-            }
-            TokenType::LessEqual => {
-                let (l, r) = get_numbers()?;
-                Ok(EvalValue::Bool(l <= r))
-            }
-            TokenType::Greater => {
-                let (l, r) = get_numbers()?;
-                Ok(EvalValue::Bool(l > r))
-            }
-            TokenType::GreaterEqual => {
-                let (l, r) = get_numbers()?;
-                Ok(EvalValue::Bool(l >= r))
+            TokenType::Less
+            | TokenType::LessEqual
+            | TokenType::Greater
+            | TokenType::GreaterEqual => {
+                // None when comparing with NaN, which makes every comparison false
+                let ordering = match get_numbers()? {
+                    Numbers::Ints(l, r) => Some(l.cmp(&r)),
+                    Numbers::Floats(l, r) => l.partial_cmp(&r),
+                };
+                let result = match binary.operator.token_type {
+                    TokenType::Less => ordering == Some(Ordering::Less),
+                    TokenType::LessEqual => {
+                        matches!(ordering, Some(Ordering::Less | Ordering::Equal))
+                    }
+                    TokenType::Greater => ordering == Some(Ordering::Greater),
+                    _ => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+                };
+                Ok(EvalValue::Bool(result))
             }
 
-            TokenType::EqualEqual  => match (&left, &right) {
-                (EvalValue::Bool(l), EvalValue::Bool(r)) => Ok(EvalValue::Bool(l == r)),
-                (EvalValue::Number(l), EvalValue::Number(r)) => Ok(EvalValue::Bool(l == r)),
-                (EvalValue::Str(l), EvalValue::Str(r)) => Ok(EvalValue::Bool(l == r)),
-                _ => Err(format!("Must be numbers, string or bool at line {}", binary.operator.line)),
-            }
-            TokenType::BangEqual => {
-                let (l, r) = get_numbers()?;
-                Ok(EvalValue::Bool(l != r))
-            }
-
-            TokenType::And => {
-                let left = self.evaluate_expr(&binary.left)?;
-                if !self.is_truthy(&left) {
-                    return Ok(left);
-                }
-
-                return Ok(self.evaluate_expr(&binary.right)?);
-            }
-            TokenType::Or => {
-                let left = self.evaluate_expr(&binary.left)?;
-                if self.is_truthy(&left) {
-                    return Ok(left);
-                }
-
-                return Ok(self.evaluate_expr(&binary.right)?);
-            }
-
-            TokenType::Minus => {
-                let (l, r) = get_numbers()?;
-                Ok(EvalValue::Number(l - r))
-            }
-            TokenType::Slash => {
-                let (l, r) = get_numbers()?;
-                Ok(EvalValue::Number(l / r))
-            }
-            TokenType::Star => {
-                let (l, r) = get_numbers()?;
-                Ok(EvalValue::Number(l * r))
-            }
+            TokenType::EqualEqual => Ok(EvalValue::Bool(self.is_equal(&left, &right))),
+            TokenType::BangEqual => Ok(EvalValue::Bool(!self.is_equal(&left, &right))),
+
+            TokenType::Minus
+            | TokenType::Star
+            | TokenType::Slash
+            | TokenType::Percent
+            | TokenType::TildeSlash => arithmetic(
+                &binary.operator.token_type,
+                get_numbers()?,
+                binary.operator.line,
+            ),
             TokenType::Plus => match (&left, &right) {
-                (EvalValue::Number(l), EvalValue::Number(r)) => Ok(EvalValue::Number(l + r)),
                 (EvalValue::Str(l), EvalValue::Str(r)) => {
                     Ok(EvalValue::Str(Rc::new(l.to_string() + r.as_ref())))
                 }
-                _ => Err(format!("Must be numbers or string at line {}", binary.operator.line)),
+                _ => match Numbers::from_values(&left, &right) {
+                    Some(numbers) => arithmetic(&TokenType::Plus, numbers, binary.operator.line),
+                    None => Err(new_runtime_error(
+                        ErrorKind::TypeMismatch,
+                        "Must be numbers or string".to_string(),
+                        binary.operator.line,
+                    )),
+                },
             },
-            _ => Err(format!("Unsupported binary operator at line{}", binary.operator.line)),
+            _ => Err(new_runtime_error(
+                ErrorKind::TypeMismatch,
+                "Unsupported binary operator".to_string(),
+                binary.operator.line,
+            )),
         }
     }
 
+    fn visit_logical(&mut self, logical: &expr::Logical) -> EvalResult {
+        let left = self.evaluate_expr(&logical.left)?;
+
+        let short_circuit = match logical.operator.token_type {
+            TokenType::Or => self.is_truthy(&left),
+            _ => !self.is_truthy(&left),
+        };
+        if short_circuit {
+            return Ok(left);
+        }
+
+        return self.evaluate_expr(&logical.right);
+    }
+
     fn visit_grouping(&mut self, grouping: &expr::Expr) -> EvalResult {
         self.evaluate_expr(grouping)
     }
@@ -276,94 +668,287 @@ impl expr::ExprVisitor<EvalResult> for InterpreterContext<'_> {
         Ok(EvalValue::Bool(!self.is_truthy(&result)))
     }
 
-    fn visit_unary_negate(&mut self, expr: &expr::Expr) -> EvalResult {
-        let result = self.evaluate_expr(expr)?;
+    fn visit_unary_negate(&mut self, negate: &expr::UnaryNegate) -> EvalResult {
+        let result = self.evaluate_expr(&negate.expr)?;
         match result {
             EvalValue::Number(n) => return Ok(EvalValue::Number(-n)),
-            _ => return Err("Unary negate expected number".to_owned()),
+            EvalValue::Int(n) => match n.checked_neg() {
+                Some(n) => return Ok(EvalValue::Int(n)),
+                None => {
+                    return Err(new_runtime_error(
+                        ErrorKind::IntegerOverflow,
+                        "Integer overflow".to_string(),
+                        negate.line,
+                    ))
+                }
+            },
+            _ => {
+                return Err(new_runtime_error(
+                    ErrorKind::TypeMismatch,
+                    "Unary negate expected number".to_string(),
+                    negate.line,
+                ))
+            }
         }
     }
 
     fn visit_variable(&mut self, variable: &expr::Variable) -> EvalResult {
-        if let Some(local_environment) = &self.local_environment {
-            if let Some(value) = local_environment.get_var(&variable.name) {
-                return Ok(value);
+        self.look_up_variable(variable.name, variable.depth.get(), variable.line)
+    }
+
+    fn visit_assignment(&mut self, assignment: &expr::Assignment) -> EvalResult {
+        let value = self.evaluate_expr(&assignment.expr)?;
+
+        let assigned = match (assignment.depth.get(), &self.local_environment) {
+            (Some(depth), Some(local_environment)) => {
+                local_environment.assign_at(depth, assignment.target, value.clone())
+            }
+            _ => self
+                .global_environment
+                .assign_var(assignment.target, value.clone()),
+        };
+
+        if !assigned {
+            return Err(new_runtime_error(
+                ErrorKind::UndefinedVariable,
+                format!("Undefined variable {}", assignment.target),
+                assignment.line,
+            ));
+        }
+
+        Ok(value)
+    }
+
+    fn visit_call(&mut self, call: &expr::Call) -> EvalResult {
+        let callee = self.evaluate_expr(&call.callee)?;
+
+        let mut arguments = vec![];
+        for arg in &call.arguments {
+            arguments.push(self.evaluate_expr(arg)?);
+        }
+
+        self.call_value(callee, &arguments, Some(call.line))
+    }
+
+    fn visit_get(&mut self, get: &expr::Get) -> EvalResult {
+        let object = self.evaluate_expr(&get.object)?;
+        match object {
+            EvalValue::Instance(instance) => {
+                match eval_value::LoxInstance::get(&instance, &get.name) {
+                    Some(value) => Ok(value),
+                    None => Err(new_runtime_error(
+                        ErrorKind::UndefinedProperty,
+                        format!("Undefined property {}", get.name),
+                        get.line,
+                    )),
+                }
             }
+            _ => Err(new_runtime_error(
+                ErrorKind::TypeMismatch,
+                "Only instances have properties".to_string(),
+                get.line,
+            )),
         }
+    }
 
-        let value = match self.global_environment.get_var(&variable.name) {
-            Some(v) => v,
-            None => {
-                return Err(format!(
-                    "Undefined variable {} at line {}",
-                    variable.name, variable.line
+    fn visit_set(&mut self, set: &expr::Set) -> EvalResult {
+        let object = self.evaluate_expr(&set.object)?;
+        let instance = match object {
+            EvalValue::Instance(instance) => instance,
+            _ => {
+                return Err(new_runtime_error(
+                    ErrorKind::TypeMismatch,
+                    "Only instances have fields".to_string(),
+                    set.line,
                 ))
             }
         };
 
+        let value = self.evaluate_expr(&set.value)?;
+        instance.set(&set.name, value.clone());
         Ok(value)
     }
 
-    fn visit_assignment(&mut self, assignment: &expr::Assignment) -> EvalResult {
-        let value = self.evaluate_expr(&assignment.expr)?;
+    fn visit_this(&mut self, this: &expr::This) -> EvalResult {
+        self.look_up_variable(symbol::THIS, this.depth.get(), this.line)
+    }
 
-        let is_target_in_local_env = {
-            if let Some(local_environment) = &self.local_environment {
-                local_environment.get_var(&assignment.target).is_some()
-            } else {
-                false
+    fn visit_super(&mut self, sup: &expr::Super) -> EvalResult {
+        let depth = sup.depth.get();
+        let superclass = match self.look_up_variable(symbol::SUPER, depth, sup.line)? {
+            EvalValue::Class(c) => c,
+            _ => {
+                return Err(new_runtime_error(
+                    ErrorKind::UndefinedVariable,
+                    "Undefined variable super".to_string(),
+                    sup.line,
+                ))
             }
         };
 
-        if is_target_in_local_env {
-            self.local_environment
-                .as_mut()
-                .unwrap()
-                .set_var(&assignment.target, value.clone());
-        } else if self
-            .global_environment
-            .get_var(&assignment.target)
-            .is_some()
-        {
-            self.global_environment
-                .set_var(&assignment.target, value.clone());
-        } else {
-            return Err(format!(
-                "Undefined variable {} at line {}",
-                assignment.target, assignment.line
-            ));
+        // 'this' is bound in the scope just inside the one holding 'super'
+        let this_depth = depth.and_then(|d| d.checked_sub(1));
+        let instance = match self.look_up_variable(symbol::THIS, this_depth, sup.line)? {
+            EvalValue::Instance(i) => i,
+            _ => {
+                return Err(new_runtime_error(
+                    ErrorKind::UndefinedVariable,
+                    "Undefined variable this".to_string(),
+                    sup.line,
+                ))
+            }
+        };
+
+        match superclass.find_method(&sup.method) {
+            Some(method) => Ok(EvalValue::Function(Rc::new(method.bind(instance)))),
+            None => Err(new_runtime_error(
+                ErrorKind::UndefinedProperty,
+                format!("Undefined property {}", sup.method),
+                sup.line,
+            )),
         }
+    }
 
-        Ok(value)
+    fn visit_lambda(&mut self, function: &Rc<stmt::Function>) -> EvalResult {
+        let lox_function = eval_value::LoxFunction {
+            declaration: function.clone(),
+            closure: self.local_environment.clone(),
+            is_initializer: false,
+        };
+
+        Ok(EvalValue::Function(Rc::new(lox_function)))
     }
 
-    fn visit_call(&mut self, call: &expr::Call) -> EvalResult {
-        let callee = self.evaluate_expr(&call.callee)?;
-        match callee {
-            EvalValue::Function(f) => {
-                if f.declaration.arity() != call.arguments.len() as u32 {
-                    return Err(format!(
-                        "Function expected {} but got {}, at line {}",
-                        f.declaration.arity(),
-                        call.arguments.len(),
-                        call.line
-                    ));
-                }
+    fn visit_list(&mut self, elements: &[expr::Expr]) -> EvalResult {
+        let mut list = vec![];
+        for element in elements {
+            list.push(self.evaluate_expr(element)?);
+        }
+
+        Ok(EvalValue::List(Rc::new(RefCell::new(list))))
+    }
+
+    fn visit_map(&mut self, map_expr: &expr::Map) -> EvalResult {
+        let mut map = BTreeMap::new();
+        for (key, value) in &map_expr.entries {
+            let key = self.evaluate_expr(key)?;
+            let key = self.map_key(&key, map_expr.line)?;
+            map.insert(key, self.evaluate_expr(value)?);
+        }
+
+        Ok(EvalValue::Map(Rc::new(RefCell::new(map))))
+    }
 
-                let mut arguments = vec![];
-                for arg in &call.arguments {
-                    arguments.push(self.evaluate_expr(arg)?);
+    fn visit_index(&mut self, index: &expr::Index) -> EvalResult {
+        let object = self.evaluate_expr(&index.object)?;
+        let i = self.evaluate_expr(&index.index)?;
+
+        match object {
+            EvalValue::List(list) => {
+                let i = self.list_index(&i, list.borrow().len(), index.line)?;
+                let value = list.borrow()[i].clone();
+                Ok(value)
+            }
+            EvalValue::Map(map) => {
+                let key = self.map_key(&i, index.line)?;
+                match map.borrow().get(&key) {
+                    Some(value) => Ok(value.clone()),
+                    None => Err(new_runtime_error(
+                        ErrorKind::MissingKey,
+                        format!("Key {} not found in map", key),
+                        index.line,
+                    )),
                 }
+            }
+            _ => Err(new_runtime_error(
+                ErrorKind::TypeMismatch,
+                "Only lists and maps can be indexed".to_string(),
+                index.line,
+            )),
+        }
+    }
+
+    fn visit_index_set(&mut self, index_set: &expr::IndexSet) -> EvalResult {
+        let object = self.evaluate_expr(&index_set.object)?;
+        let i = self.evaluate_expr(&index_set.index)?;
+        let value = self.evaluate_expr(&index_set.value)?;
 
-                return Ok(eval_value::LoxFunction::call(f, &mut self.global_environment, &arguments)?);
+        match object {
+            EvalValue::List(list) => {
+                let i = self.list_index(&i, list.borrow().len(), index_set.line)?;
+                list.borrow_mut()[i] = value.clone();
+            }
+            EvalValue::Map(map) => {
+                let key = self.map_key(&i, index_set.line)?;
+                map.borrow_mut().insert(key, value.clone());
+            }
+            _ => {
+                return Err(new_runtime_error(
+                    ErrorKind::TypeMismatch,
+                    "Only lists and maps can be indexed".to_string(),
+                    index_set.line,
+                ))
             }
-            _ => {}
         }
 
-        Err(format!("Not a callable object at line {}", call.line))
+        Ok(value)
     }
 
     fn visit_nil(&self) -> EvalResult {
         return Ok(EvalValue::Nil);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::builtins;
+    use crate::parser;
+    use crate::resolver;
+    use crate::scanner;
+
+    // evaluates a source made of a single expression statement
+    fn evaluate(source: &str) -> EvalResult {
+        let tokens = scanner::scan(source).unwrap();
+        let stmts = parser::parse(&tokens).unwrap();
+        resolver::resolve(&stmts).unwrap();
+
+        let mut global_environment = Environment::new();
+        builtins::define_globals(&mut global_environment);
+        let mut output = std::io::sink();
+        let mut interpreter = InterpreterContext::new(&mut global_environment, &mut output);
+        match &stmts[..] {
+            [stmt::Stmt {
+                kind: stmt::StmtKind::Expr(expr),
+                ..
+            }] => interpreter.evaluate_expr(expr),
+            _ => panic!("Expected a single expression in {}", source),
+        }
+    }
+
+    #[test]
+    fn native_function_call() {
+        assert!(matches!(evaluate("clock();"), Ok(EvalValue::Number(_))));
+        assert!(matches!(evaluate("len(\"abc\");"), Ok(EvalValue::Int(3))));
+    }
+
+    #[test]
+    fn native_function_arity() {
+        let error = evaluate("clock(1);").unwrap_err();
+        assert_eq!(error.detail().kind, ErrorKind::ArityMismatch);
+        assert_eq!(error.detail().line, Some(1));
+
+        let error = evaluate("len();").unwrap_err();
+        assert_eq!(error.detail().kind, ErrorKind::ArityMismatch);
+    }
+
+    #[test]
+    fn native_function_error() {
+        let error = evaluate("len(1);").unwrap_err();
+        assert_eq!(error.detail().kind, ErrorKind::NativeError);
+        assert_eq!(
+            error.detail().message,
+            "len() expects a list, map or string"
+        );
+    }
+}
@@ -0,0 +1,23 @@
+#![allow(clippy::needless_return)]
+
+mod builtins;
+pub mod diagnostic;
+mod environment;
+pub mod error;
+mod eval_value;
+mod expr;
+mod interpreter;
+mod lox;
+mod parser;
+mod resolver;
+mod scanner;
+mod stack;
+mod stmt;
+mod symbol;
+mod token;
+
+pub use error::LoxError;
+pub use eval_value::EvalValue as Value;
+pub use lox::{Lox, OutputBuffer};
+pub use scanner::keywords;
+pub use token::Span;
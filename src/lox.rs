@@ -1,65 +1,351 @@
-use std::io::Read;
+use std::cell::RefCell;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+use std::rc::Rc;
 
+use crate::builtins;
+use crate::diagnostic;
 use crate::environment::Environment;
+use crate::error::{ErrorDetail, ErrorKind, LoxError};
+use crate::eval_value::EvalValue;
 use crate::interpreter::InterpreterContext;
 use crate::parser;
+use crate::resolver;
 use crate::scanner;
+use crate::stmt;
+use crate::symbol::Symbol;
 
-pub fn lox_main(args: &[String]) {
-    if args.len() > 1 {
-        println!("Usage: lox [script]");
-    } else if args.len() == 1 {
-        run_file(&args[0]);
-    } else {
-        run_prompt();
+// An interpreter session for embedding lox. Globals defined by one call to
+// 'eval' are visible to the next.
+//
+// let mut lox = Lox::new();
+// lox.eval("fun add(a, b) { return a + b; }")?;
+// let sum = lox.call("add", &[1.0.into(), 2.0.into()])?;
+pub struct Lox {
+    global_environment: Environment,
+    // where 'print' writes to, stdout unless set
+    output: Box<dyn Write>,
+    // where 'report' writes diagnostics to, stderr unless set
+    error_output: Box<dyn Write>,
+    color: bool,
+}
+
+// A Write that keeps what was written for reading back, clones share the
+// same buffer so one can be handed to a Lox and the other kept
+//
+// let output = OutputBuffer::new();
+// lox.set_output(output.clone());
+// lox.eval("print 1;")?;
+// assert_eq!(output.contents(), "1 \n");
+#[derive(Clone, Default)]
+pub struct OutputBuffer {
+    buffer: Rc<RefCell<Vec<u8>>>,
+}
+
+impl OutputBuffer {
+    pub fn new() -> OutputBuffer {
+        OutputBuffer::default()
+    }
+
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.buffer.borrow()).into_owned()
+    }
+
+    pub fn clear(&self) {
+        self.buffer.borrow_mut().clear();
     }
 }
 
-fn run(interpreter: &mut InterpreterContext, source: &str) -> Result<(), std::vec::Vec<String>> {
-    let tokens = scanner::scan(source)?;
-    let stmts = parser::parse(&tokens)?;
+impl Write for OutputBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.borrow_mut().write(buf)
+    }
 
-    if let Err(e) = interpreter.interpret(&stmts) {
-        return Err(vec![e]);
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
     }
-    Ok(())
 }
 
-fn run_file(filename: &str) {
-    if let Ok(mut file) = std::fs::File::open(filename) {
-        let mut buf = String::new();
-        if let Err(e) = file.read_to_string(&mut buf) {
-            eprintln!("Failed to read from file: {}", e);
-            return;
-        }
+impl Default for Lox {
+    fn default() -> Lox {
+        Lox::new()
+    }
+}
 
+impl Lox {
+    // A session with the native functions already defined
+    pub fn new() -> Lox {
         let mut global_environment = Environment::new();
-        let mut interpreter = InterpreterContext::new(&mut global_environment);
-        if let Err(e) = run(&mut interpreter, &buf) {
-            eprintln!("Error: {}", e[0]);
+        builtins::define_globals(&mut global_environment);
+        Lox {
+            global_environment,
+            output: Box::new(std::io::stdout()),
+            error_output: Box::new(std::io::stderr()),
+            color: std::io::stderr().is_terminal(),
         }
-    } else {
-        eprintln!("Failed to open file '{}'", filename);
     }
 
-    //    run();
-}
+    pub fn set_output(&mut self, output: impl Write + 'static) {
+        self.output = Box::new(output);
+    }
 
-fn run_prompt() {
-    let mut global_environment = Environment::new();
-    let mut interpreter = InterpreterContext::new(&mut global_environment);
+    // Diagnostics written here are never colored
+    pub fn set_error_output(&mut self, error_output: impl Write + 'static) {
+        self.error_output = Box::new(error_output);
+        self.color = false;
+    }
 
-    let mut line = String::new();
-    loop {
-        eprint!(":> ");
-        if let Err(_) = std::io::stdin().read_line(&mut line) {
-            return;
+    // Writes the errors to the error output with the source lines they point at
+    pub fn report(&mut self, errors: &[LoxError], file_name: &str, source: &str) {
+        for error in errors {
+            let rendered = diagnostic::render(error, file_name, source, self.color);
+            // there's nowhere left to report a failure to write diagnostics
+            let _ = self.error_output.write_all(rendered.as_bytes());
         }
+        let _ = self.error_output.flush();
+    }
+
+    // Runs the source and returns the value of its last statement when that
+    // is an expression, nil otherwise. Only the first error is returned
+    pub fn eval(&mut self, source: &str) -> Result<EvalValue, LoxError> {
+        self.run(source)
+            .map(|value| value.unwrap_or(EvalValue::Nil))
+            .map_err(|mut errors| errors.swap_remove(0))
+    }
+
+    // Like 'eval', but returns every error found before running the source,
+    // and None rather than nil when the source doesn't end in an expression
+    pub fn run(&mut self, source: &str) -> Result<Option<EvalValue>, Vec<LoxError>> {
+        let mut stmts = compile(source)?;
+
+        let last_expr = match stmts.last() {
+            Some(stmt::Stmt {
+                kind: stmt::StmtKind::Expr(_),
+                ..
+            }) => stmts.pop(),
+            _ => None,
+        };
+
+        let mut interpreter =
+            InterpreterContext::new(&mut self.global_environment, &mut *self.output);
+        interpreter.interpret(&stmts).map_err(|e| vec![e])?;
+
+        match last_expr {
+            Some(stmt::Stmt {
+                kind: stmt::StmtKind::Expr(expr),
+                ..
+            }) => interpreter
+                .evaluate_expr(&expr)
+                .map(Some)
+                .map_err(|e| vec![e]),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn run_file(&mut self, path: impl AsRef<Path>) -> Result<EvalValue, LoxError> {
+        let path = path.as_ref();
+        match std::fs::read_to_string(path) {
+            Ok(source) => self.eval(&source),
+            Err(e) => Err(LoxError::Io(Box::new(ErrorDetail::new(
+                ErrorKind::Io,
+                format!("Failed to read file '{}': {}", path.display(), e),
+            )))),
+        }
+    }
+
+    // Finds every error it can without running anything
+    pub fn check(source: &str) -> Result<(), Vec<LoxError>> {
+        compile(source).map(|_| ())
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<EvalValue> {
+        self.global_environment.get_var(Symbol::intern(name))
+    }
+
+    // Defines the global, replacing any value it already has
+    pub fn set_global(&mut self, name: &str, value: EvalValue) {
+        self.global_environment
+            .define_var(Symbol::intern(name), value);
+    }
 
-        if let Err(e) = run(&mut interpreter, &line) {
-            eprintln!("Error: {}", e[0]);
+    // every global, in the order they were defined
+    pub fn globals(&self) -> Vec<(String, EvalValue)> {
+        self.global_environment
+            .entries()
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect()
+    }
+
+    // Calls the global function or class called 'name'
+    pub fn call(&mut self, name: &str, arguments: &[EvalValue]) -> Result<EvalValue, LoxError> {
+        let callee = match self.get_global(name) {
+            Some(callee) => callee,
+            None => {
+                return Err(LoxError::Runtime(Box::new(ErrorDetail::new(
+                    ErrorKind::UndefinedVariable,
+                    format!("Undefined variable {}", name),
+                ))))
+            }
+        };
+
+        let mut interpreter =
+            InterpreterContext::new(&mut self.global_environment, &mut *self.output);
+        interpreter.call_value(callee, arguments, None)
+    }
+}
+
+fn compile(source: &str) -> Result<Vec<stmt::Stmt>, Vec<LoxError>> {
+    let tokens = scanner::scan(source)?;
+    let stmts = parser::parse(&tokens)?;
+    resolver::resolve(&stmts)?;
+    Ok(stmts)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn eval_keeps_globals() {
+        let mut lox = Lox::new();
+        assert!(matches!(lox.eval("var a = 1;"), Ok(EvalValue::Nil)));
+        assert!(matches!(lox.eval("a + 2;"), Ok(EvalValue::Int(3))));
+        assert!(matches!(lox.get_global("a"), Some(EvalValue::Int(1))));
+
+        lox.set_global("b", EvalValue::from("text"));
+        assert_eq!(lox.eval("b;").unwrap().to_string(), "text");
+        assert!(lox.get_global("c").is_none());
+    }
+
+    #[test]
+    fn run_trailing_expression() {
+        let mut lox = Lox::new();
+        assert!(matches!(lox.run("var a = nil;"), Ok(None)));
+        assert!(matches!(lox.run("a;"), Ok(Some(EvalValue::Nil))));
+        assert!(matches!(lox.run("a; 1;"), Ok(Some(EvalValue::Int(1)))));
+    }
+
+    #[test]
+    fn eval_errors() {
+        let mut lox = Lox::new();
+        let error = lox.eval("print 1 +; print 2 +;").unwrap_err();
+        assert_eq!(error.detail().kind, ErrorKind::UnexpectedToken);
+        assert_eq!(lox.run("print 1 +; print 2 +;").unwrap_err().len(), 2);
+
+        assert!(matches!(lox.eval("-nil;"), Err(LoxError::Runtime(_))));
+        assert!(matches!(
+            lox.run_file("does/not/exist.lox"),
+            Err(LoxError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn call_by_name() {
+        let mut lox = Lox::new();
+        lox.eval(
+            "fun add(a, b) { return a + b; }
+             class Point { init(x) { this.x = x; } }",
+        )
+        .unwrap();
+
+        let sum = lox.call("add", &[1.0.into(), 2.0.into()]).unwrap();
+        assert!(matches!(sum, EvalValue::Number(n) if n == 3.0));
+
+        let point = lox.call("Point", &[5.0.into()]).unwrap();
+        assert_eq!(point.to_string(), "Lox instance <Point>");
+
+        let error = lox.call("add", &[1.0.into()]).unwrap_err();
+        assert_eq!(error.detail().kind, ErrorKind::ArityMismatch);
+        let error = lox.call("missing", &[]).unwrap_err();
+        assert_eq!(error.detail().kind, ErrorKind::UndefinedVariable);
+    }
+
+    #[test]
+    fn captured_output() {
+        let output = OutputBuffer::new();
+        let errors = OutputBuffer::new();
+        let mut lox = Lox::new();
+        lox.set_output(output.clone());
+        lox.set_error_output(errors.clone());
+
+        lox.eval("print 1, \"two\"; fun f() { print 3; } f();")
+            .unwrap();
+        assert_eq!(output.contents(), "1 two \n3 \n");
+
+        let source = "print -nil;";
+        let error = lox.run(source).unwrap_err();
+        lox.report(&error, "script.lox", source);
+        assert!(errors
+            .contents()
+            .starts_with("error: Unary negate expected number\n --> script.lox:1:7\n"));
+    }
+
+    #[test]
+    fn output_write_error() {
+        struct Closed;
+        impl Write for Closed {
+            fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::BrokenPipe.into())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
         }
 
-        line.clear();
+        let mut lox = Lox::new();
+        lox.set_output(Closed);
+        let error = lox.eval("print 1;").unwrap_err();
+        assert!(matches!(&error, LoxError::Runtime(detail) if detail.kind == ErrorKind::Io));
+    }
+
+    #[test]
+    fn call_traceback() {
+        let mut lox = Lox::new();
+        lox.eval("fun inner() { return -nil; }\nfun outer() { inner(); }")
+            .unwrap();
+
+        let error = lox.call("outer", &[]).unwrap_err();
+        let trace: Vec<String> = error
+            .detail()
+            .trace
+            .iter()
+            .map(|frame| frame.to_string())
+            .collect();
+        assert_eq!(trace, vec!["[line 1] in inner", "[line 2] in outer"]);
+    }
+
+    #[test]
+    fn deep_recursion_on_a_spawned_thread() {
+        // threads get a much smaller stack than the main thread by default
+        let results = std::thread::spawn(|| {
+            let sources = [
+                "fun f(n) { if (n == 0) return 0; { { if (true) { return 1 + ((f(n - 1))); } } } }\n\
+                 f(999) == 999;",
+                "fun f(n) { return 1 + f(n + 1); }\nf(0);",
+                &format!("{}1{};", "(".repeat(100_000), ")".repeat(100_000)),
+                &format!("{}{}", "{".repeat(100_000), "}".repeat(100_000)),
+            ];
+
+            let mut lox = Lox::new();
+            sources
+                .iter()
+                .map(|source| match lox.eval(source) {
+                    Ok(value) => Ok(value.to_string()),
+                    Err(error) => Err(error.detail().kind),
+                })
+                .collect::<Vec<_>>()
+        })
+        .join()
+        .unwrap();
+
+        // how deep the first one gets depends on the build
+        assert!(matches!(
+            results[0].as_deref(),
+            Ok("true") | Err(ErrorKind::StackOverflow)
+        ));
+        assert_eq!(results[1], Err(ErrorKind::StackOverflow));
+        assert_eq!(results[2], Err(ErrorKind::StackOverflow));
+        assert_eq!(results[3], Err(ErrorKind::StackOverflow));
     }
 }
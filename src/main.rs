@@ -1,16 +1,260 @@
-mod environment;
-mod eval_value;
-mod expr;
-mod interpreter;
-mod lox;
-mod parser;
-mod scanner;
-mod stmt;
-mod token;
+#![allow(clippy::needless_return)]
 
+mod repl;
+
+use rlox_tree_walker::error::{ErrorDetail, ErrorKind};
+use rlox_tree_walker::{Lox, LoxError};
+use std::io::Read;
 use std::{env, vec::Vec};
 
+// exit codes, following sysexits.h
+const EX_OK: i32 = 0;
+const EX_USAGE: i32 = 64;
+const EX_DATAERR: i32 = 65;
+const EX_NOINPUT: i32 = 66;
+const EX_SOFTWARE: i32 = 70;
+
+const USAGE: &str = "\
+Usage: lox [options] [run] [script | -e <code> | -]
+
+Runs a lox script, with no script an interactive prompt is started.
+
+Options:
+  -e <code>    run <code> instead of a script
+  -            read the script from stdin
+  --check      check the script for errors without running it
+  -h, --help   print this message
+
+Exit codes: 64 bad usage, 65 error in the script, 66 script can't be read,
+70 runtime error";
+
+#[derive(Debug, PartialEq)]
+enum Input {
+    File(String),
+    Inline(String),
+    Stdin,
+}
+
+impl Input {
+    // what diagnostics call the script
+    fn name(&self) -> &str {
+        match self {
+            Input::File(filename) => filename,
+            Input::Inline(_) => "<eval>",
+            Input::Stdin => "<stdin>",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Run(Input),
+    Check(Input),
+    Prompt,
+    Help,
+}
+
+// Returns the process exit code
+fn lox_main(args: &[String]) -> i32 {
+    let command = match parse_args(args) {
+        Ok(command) => command,
+        Err(message) => {
+            eprintln!("Error: {}\n\n{}", message, USAGE);
+            return EX_USAGE;
+        }
+    };
+
+    match command {
+        Command::Help => {
+            println!("{}", USAGE);
+            return EX_OK;
+        }
+        Command::Prompt => {
+            repl::run_prompt();
+            return EX_OK;
+        }
+        Command::Run(input) => return run_input(&mut Lox::new(), &input, false),
+        Command::Check(input) => return run_input(&mut Lox::new(), &input, true),
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Command, String> {
+    let mut check = false;
+    let mut input = None;
+
+    let mut args = args.iter().peekable();
+    if args.peek().map(|arg| arg.as_str()) == Some("run") {
+        args.next();
+    }
+
+    while let Some(arg) = args.next() {
+        let next_input = match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--check" => {
+                check = true;
+                continue;
+            }
+            "-e" => match args.next() {
+                Some(code) => Input::Inline(code.clone()),
+                None => return Err("'-e' expects the code to run".to_string()),
+            },
+            "-" => Input::Stdin,
+            arg if arg.starts_with('-') => return Err(format!("Unknown option '{}'", arg)),
+            arg => Input::File(arg.to_string()),
+        };
+
+        if input.is_some() {
+            return Err("Only one script can be run at a time".to_string());
+        }
+        input = Some(next_input);
+    }
+
+    match (input, check) {
+        (Some(input), false) => Ok(Command::Run(input)),
+        (Some(input), true) => Ok(Command::Check(input)),
+        (None, false) => Ok(Command::Prompt),
+        (None, true) => Err("'--check' expects a script to check".to_string()),
+    }
+}
+
+fn read_input(input: &Input) -> Result<String, String> {
+    match input {
+        Input::File(filename) => match std::fs::read_to_string(filename) {
+            Ok(source) => Ok(source),
+            Err(e) => Err(format!("Failed to read file '{}': {}", filename, e)),
+        },
+        Input::Inline(code) => Ok(code.clone()),
+        Input::Stdin => {
+            let mut source = String::new();
+            match std::io::stdin().read_to_string(&mut source) {
+                Ok(_) => Ok(source),
+                Err(e) => Err(format!("Failed to read from stdin: {}", e)),
+            }
+        }
+    }
+}
+
+// Output and diagnostics go wherever 'lox' writes them
+fn run_input(lox: &mut Lox, input: &Input, check_only: bool) -> i32 {
+    let source = match read_input(input) {
+        Ok(source) => source,
+        Err(message) => {
+            let error = LoxError::Io(Box::new(ErrorDetail::new(ErrorKind::Io, message)));
+            lox.report(&[error], input.name(), "");
+            return EX_NOINPUT;
+        }
+    };
+
+    let result = if check_only {
+        Lox::check(&source)
+    } else {
+        lox.run(&source).map(|_| ())
+    };
+
+    match result {
+        Ok(()) => EX_OK,
+        Err(errors) => {
+            lox.report(&errors, input.name(), &source);
+            exit_code(&errors)
+        }
+    }
+}
+
+fn exit_code(errors: &[LoxError]) -> i32 {
+    if errors.iter().any(|e| matches!(e, LoxError::Runtime(_))) {
+        EX_SOFTWARE
+    } else {
+        EX_DATAERR
+    }
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
-    lox::lox_main(&args[1..]);
+    std::process::exit(lox_main(&args[1..]));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rlox_tree_walker::OutputBuffer;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(parse_args(&args(&[])), Ok(Command::Prompt));
+        assert_eq!(
+            parse_args(&args(&["main.lox"])),
+            Ok(Command::Run(Input::File("main.lox".to_string())))
+        );
+        assert_eq!(
+            parse_args(&args(&["run", "main.lox"])),
+            Ok(Command::Run(Input::File("main.lox".to_string())))
+        );
+        assert_eq!(
+            parse_args(&args(&["-e", "print 1;"])),
+            Ok(Command::Run(Input::Inline("print 1;".to_string())))
+        );
+        assert_eq!(
+            parse_args(&args(&["--check", "-"])),
+            Ok(Command::Check(Input::Stdin))
+        );
+        assert_eq!(parse_args(&args(&["a.lox", "--help"])), Ok(Command::Help));
+    }
+
+    #[test]
+    fn bad_usage() {
+        assert!(parse_args(&args(&["a.lox", "b.lox"])).is_err());
+        assert!(parse_args(&args(&["-e"])).is_err());
+        assert!(parse_args(&args(&["--check"])).is_err());
+        assert!(parse_args(&args(&["--verbose", "a.lox"])).is_err());
+    }
+
+    // runs the input with its output and diagnostics captured, returning
+    // the exit code, the output and the diagnostics
+    fn run(input: Input, check_only: bool) -> (i32, String, String) {
+        let output = OutputBuffer::new();
+        let error_output = OutputBuffer::new();
+        let mut lox = Lox::new();
+        lox.set_output(output.clone());
+        lox.set_error_output(error_output.clone());
+
+        let code = run_input(&mut lox, &input, check_only);
+        (code, output.contents(), error_output.contents())
+    }
+
+    fn inline(code: &str) -> Input {
+        Input::Inline(code.to_string())
+    }
+
+    #[test]
+    fn exit_codes() {
+        assert_eq!(
+            run(inline("print 1 + 2;"), false),
+            (EX_OK, "3 \n".to_string(), String::new())
+        );
+
+        let (code, output, errors) = run(inline("print 1 +;"), false);
+        assert_eq!((code, output.as_str()), (EX_DATAERR, ""));
+        assert!(errors.starts_with("error: Expected primary expression, found ;\n"));
+
+        let (code, _, errors) = run(inline("{ var a = a; }"), true);
+        assert_eq!(code, EX_DATAERR);
+        assert!(errors.contains("--> <eval>:1:11"));
+
+        assert_eq!(
+            run(inline("print -nil;"), true),
+            (EX_OK, String::new(), String::new())
+        );
+
+        let (code, _, errors) = run(inline("print 1;\nprint -nil;"), false);
+        assert_eq!(code, EX_SOFTWARE);
+        assert!(errors.starts_with("error: Unary negate expected number\n"));
+
+        let (code, _, errors) = run(Input::File("does/not/exist.lox".to_string()), false);
+        assert_eq!(code, EX_NOINPUT);
+        assert!(errors.starts_with("error: Failed to read file 'does/not/exist.lox'"));
+    }
 }
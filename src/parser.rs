@@ -1,34 +1,111 @@
+use crate::error::{ErrorDetail, ErrorKind, Hint, LoxError};
 use crate::expr;
+use crate::stack;
 use crate::stmt;
-use crate::token::{Token, TokenType};
+use crate::symbol::Symbol;
+use crate::token::{Span, Token, TokenType};
 use std::iter::Peekable;
 use std::slice::Iter;
 use std::vec::Vec;
 
+// Stack kept free for reporting errors when the source is nested too deeply
+const STACK_RESERVE: usize = 128 * 1024;
+
 struct Parser<'a> {
     iter: Peekable<Iter<'a, Token>>,
+    // the last token consumed, used to find where a node ends
+    previous: Option<&'a Token>,
+    // number of tokens consumed so far, used to tell if a failed statement made progress
+    consumed: usize,
+    loop_depth: u32,
+    // number of blocks and function bodies being parsed
+    block_depth: u32,
+    // set while parsing the body of a function declared inside a loop
+    in_function_in_loop: bool,
+    // errors from statements that were skipped over to keep parsing
+    errors: Vec<LoxError>,
+    // set when the source is nested too deeply to parse, the rest is skipped
+    too_deep: bool,
 }
 
-type ExprResult = Result<expr::Expr, Vec<String>>;
-type StmtResult = Result<stmt::Stmt, Vec<String>>;
-pub fn parse(tokens: &[Token]) -> Result<Vec<stmt::Stmt>, Vec<String>> {
+type ExprResult = Result<expr::Expr, Vec<LoxError>>;
+type StmtResult = Result<stmt::Stmt, Vec<LoxError>>;
+pub fn parse(tokens: &[Token]) -> Result<Vec<stmt::Stmt>, Vec<LoxError>> {
     let mut parser = Parser {
         iter: tokens.iter().peekable(),
+        previous: None,
+        consumed: 0,
+        loop_depth: 0,
+        block_depth: 0,
+        in_function_in_loop: false,
+        errors: vec![],
+        too_deep: false,
     };
 
     let mut stmts = vec![];
-    while let Some(token) = parser.iter.peek() {
-        if token.token_type == TokenType::Eof {
-            break;
+    while !parser.is_at_end() {
+        if let Some(stmt) = parser.declaration() {
+            stmts.push(stmt);
         }
+    }
 
-        stmts.push(parser.statement()?);
+    if !parser.errors.is_empty() {
+        return Err(parser.errors);
     }
 
     return Ok(stmts);
 }
 
 impl<'a> Parser<'a> {
+    fn advance(&mut self) -> Option<&'a Token> {
+        let token = self.iter.next();
+        if token.is_some() {
+            self.previous = token;
+            self.consumed += 1;
+        }
+        token
+    }
+
+    // an error at the end of the source, placed at the Eof token when there is one
+    fn error_at_end(&mut self, message: &str) -> Vec<LoxError> {
+        match self.iter.peek() {
+            Some(token) => vec![LoxError::Parse(Box::new(ErrorDetail {
+                span: Some(token.span),
+                ..ErrorDetail::at_line(ErrorKind::UnexpectedEof, message.to_string(), token.line)
+            }))],
+            None => error_at_eof(message),
+        }
+    }
+
+    fn is_at_end(&mut self) -> bool {
+        match self.iter.peek() {
+            Some(token) => token.token_type == TokenType::Eof,
+            None => true,
+        }
+    }
+
+    // byte offset where the next node starts
+    fn start(&mut self) -> usize {
+        match self.iter.peek() {
+            Some(token) => token.span.start,
+            None => self.previous.map_or(0, |token| token.span.end),
+        }
+    }
+
+    fn span_from(&self, start: usize) -> Span {
+        Span {
+            start,
+            end: self
+                .previous
+                .map_or(start, |token| token.span.end.max(start)),
+        }
+    }
+
+    fn finish_expr(&self, start: usize, mut expr: expr::Expr) -> expr::Expr {
+        expr.span = self.span_from(start);
+        expr
+    }
+
     fn check(&mut self, token_type: &TokenType) -> bool {
         if let Some(t) = self.iter.peek() {
             return t.token_type == *token_type;
@@ -37,10 +114,17 @@ impl<'a> Parser<'a> {
         return false;
     }
 
+    fn is_next_token(&self, lookahead: usize, token_type: &TokenType) -> bool {
+        match self.iter.clone().nth(lookahead) {
+            Some(t) => t.token_type == *token_type,
+            None => false,
+        }
+    }
+
     fn match_tokens(&mut self, token_types: &[TokenType]) -> Option<Token> {
         for token_type in token_types {
             if self.check(token_type) {
-                return Some(self.iter.next().unwrap().clone());
+                return Some(self.advance().unwrap().clone());
             }
         }
 
@@ -51,58 +135,166 @@ impl<'a> Parser<'a> {
         &mut self,
         token_type: TokenType,
         error_message: &str,
-    ) -> Result<(), Vec<String>> {
+    ) -> Result<(), Vec<LoxError>> {
         if self.check(&token_type) {
-            self.iter.next();
+            self.advance();
             return Ok(());
         }
 
         if let Some(token) = self.iter.peek() {
-            Err(vec![format!(
-                "Line {} at '{}': {}",
-                token.line,
-                (**token).to_string(),
-                error_message.to_string()
-            )])
+            Err(error_at_token(token, error_message))
         } else {
-            Err(vec![format!("At EOF: {}", error_message.to_string())])
+            Err(error(
+                ErrorKind::UnexpectedEof,
+                format!("At EOF: {}", error_message),
+            ))
+        }
+    }
+
+    // Parses a statement, on a syntax error the error is recorded and the
+    // parser skips ahead to where the next statement likely starts
+    fn declaration(&mut self) -> Option<stmt::Stmt> {
+        let consumed = self.consumed;
+        match self.statement() {
+            Ok(stmt) => Some(stmt),
+            // what's left open around the deeply nested part fails too, but
+            // only because the rest of the source was skipped
+            Err(_) if self.too_deep => None,
+            Err(errors) => {
+                self.too_deep = errors
+                    .iter()
+                    .any(|e| e.detail().kind == ErrorKind::StackOverflow);
+                self.errors.extend(errors);
+                if self.too_deep {
+                    while !self.is_at_end() {
+                        self.advance();
+                    }
+                    return None;
+                }
+
+                if self.consumed == consumed {
+                    self.advance();
+                }
+                self.synchronize();
+                None
+            }
+        }
+    }
+
+    fn synchronize(&mut self) {
+        if let Some(token) = self.previous {
+            if token.token_type == TokenType::SemiColon {
+                return;
+            }
+        }
+
+        while let Some(token) = self.iter.peek() {
+            match token.token_type {
+                TokenType::SemiColon => {
+                    self.advance();
+                    return;
+                }
+                // a '}' closes the enclosing block, at the top level it can
+                // only be left over from a statement that failed to parse
+                TokenType::RightBrace if self.block_depth == 0 => {
+                    self.advance();
+                }
+                // the body of a statement whose header failed to parse is
+                // parsed as a block, so errors inside it are still found
+                TokenType::Eof
+                | TokenType::RightBrace
+                | TokenType::LeftBrace
+                | TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return
+                | TokenType::Break
+                | TokenType::Continue => return,
+                _ => {
+                    self.advance();
+                }
+            }
+        }
+    }
+
+    // Nested statements and expressions are parsed recursively, so deeply
+    // nested source is an error rather than a native stack overflow
+    fn check_nesting(&mut self) -> Result<(), Vec<LoxError>> {
+        if !stack::remaining_below(STACK_RESERVE) {
+            return Ok(());
+        }
+
+        let message = "Too deeply nested";
+        match self.iter.peek() {
+            Some(token) => Err(vec![LoxError::Parse(Box::new(ErrorDetail::at_token(
+                ErrorKind::StackOverflow,
+                message.to_string(),
+                token,
+            )))]),
+            None => Err(error(ErrorKind::StackOverflow, message.to_string())),
         }
     }
 
     fn statement(&mut self) -> StmtResult {
+        self.check_nesting()?;
+        let start = self.start();
+        let mut stmt = self.statement_without_span()?;
+        stmt.span = self.span_from(start);
+
+        Ok(stmt)
+    }
+
+    fn statement_without_span(&mut self) -> StmtResult {
+        // 'fun' directly followed by '(' starts an anonymous function expression
+        let is_lambda = self.is_next_token(1, &TokenType::LeftParen);
+        // a '{' whose first element is followed by ':' starts a map literal, not a block
+        let is_map = self.is_next_token(2, &TokenType::Colon);
+
         if let Some(token) = self.iter.peek() {
             match token.token_type {
                 TokenType::If => {
-                    self.iter.next();
+                    self.advance();
                     return self.if_stmt();
                 }
-                TokenType::LeftBrace => {
-                    self.iter.next();
+                TokenType::LeftBrace if !is_map => {
+                    self.advance();
                     return self.block_stmt();
                 }
                 TokenType::Print => {
-                    self.iter.next();
+                    self.advance();
                     return self.print_stmt();
                 }
                 TokenType::Var => {
-                    self.iter.next();
+                    self.advance();
                     return self.var_stmt();
                 }
                 TokenType::While => {
-                    self.iter.next();
+                    self.advance();
                     return self.while_stmt();
                 }
                 TokenType::For => {
-                    self.iter.next();
+                    self.advance();
                     return self.for_stmt();
                 }
-                TokenType::Fun => {
-                    self.iter.next();
+                TokenType::Fun if !is_lambda => {
+                    self.advance();
                     return self.function_stmt();
                 }
                 TokenType::Return => {
-                    self.iter.next();
-                    return self.return_stmt();
+                    let line = self.advance().unwrap().line;
+                    return self.return_stmt(line);
+                }
+                TokenType::Class => {
+                    self.advance();
+                    return self.class_stmt();
+                }
+                TokenType::Break | TokenType::Continue => {
+                    let token = self.advance().unwrap();
+                    return self.loop_control_stmt(token);
                 }
                 _ => {}
             }
@@ -113,45 +305,56 @@ impl<'a> Parser<'a> {
 
     fn function_stmt(&mut self) -> StmtResult {
         let (name, line) = {
-            let next_token = match self.iter.next() {
+            let next_token = match self.advance() {
                 Some(t) => t,
-                None => return Err(vec!["Expected identifer after 'fun', found EOF".to_string()]),
+                None => return Err(error_at_eof("Expected identifer after 'fun', found EOF")),
             };
 
             match &next_token.token_type {
                 TokenType::Identifier(identifier) => (identifier, next_token.line),
                 _ => {
-                    return Err(vec![format!(
-                        "Expected identifier after 'fun', found {}",
-                        next_token.to_string()
-                    )])
+                    return Err(error_at_token(
+                        next_token,
+                        &format!(
+                            "Expected identifier after 'fun', found {}",
+                            next_token.lexeme()
+                        ),
+                    ))
                 }
             }
         };
 
         self.consume_token(TokenType::LeftParen, "Expected '(' after function name")?;
 
+        let (parameters, statements) = self.function_body()?;
+
+        Ok(stmt::new_function(name, parameters, statements, line))
+    }
+
+    // parses everything after the opening '(' of a function's parameter list
+    fn function_body(&mut self) -> Result<(Vec<Symbol>, Vec<stmt::Stmt>), Vec<LoxError>> {
         let mut parameters = vec![];
 
         if self.match_tokens(&[TokenType::RightParen]).is_none() {
             loop {
                 let parameter = {
-                    let next_token = match self.iter.next() {
+                    let next_token = match self.advance() {
                         Some(t) => t,
                         None => {
-                            return Err(vec![
-                                "Expected identifer after 'fun', found EOF".to_string()
-                            ])
+                            return Err(error_at_eof("Expected identifer after 'fun', found EOF"))
                         }
                     };
 
                     match &next_token.token_type {
-                        TokenType::Identifier(identifier) => identifier.clone(),
+                        TokenType::Identifier(identifier) => Symbol::intern(identifier),
                         _ => {
-                            return Err(vec![format!(
-                                "Expected identifier after 'fun', found {}",
-                                next_token.to_string()
-                            )])
+                            return Err(error_at_token(
+                                next_token,
+                                &format!(
+                                    "Expected identifier after 'fun', found {}",
+                                    next_token.lexeme()
+                                ),
+                            ))
                         }
                     }
                 };
@@ -173,23 +376,110 @@ impl<'a> Parser<'a> {
             "Expected '{' after function parameters",
         )?;
 
+        // 'break' and 'continue' can't reach a loop outside the function
+        let enclosing_loop_depth = self.loop_depth;
+        let enclosing_in_function_in_loop = self.in_function_in_loop;
+        self.in_function_in_loop |= self.loop_depth > 0;
+        self.loop_depth = 0;
+        let statements = self.block_statements();
+        self.loop_depth = enclosing_loop_depth;
+        self.in_function_in_loop = enclosing_in_function_in_loop;
+
+        Ok((parameters, statements?))
+    }
+
+    fn block_statements(&mut self) -> Result<Vec<stmt::Stmt>, Vec<LoxError>> {
+        self.block_depth += 1;
         let mut statements = vec![];
+        let closed = loop {
+            if self.match_tokens(&[TokenType::RightBrace]).is_some() {
+                break true;
+            }
+            if self.is_at_end() {
+                break false;
+            }
+
+            if let Some(stmt) = self.declaration() {
+                statements.push(stmt);
+            }
+        };
+        self.block_depth -= 1;
+
+        if !closed {
+            return Err(self.error_at_end("Expected '}' after block, found EOF"));
+        }
+
+        Ok(statements)
+    }
+
+    fn class_stmt(&mut self) -> StmtResult {
+        let (name, line) = {
+            let next_token = match self.advance() {
+                Some(t) => t,
+                None => return Err(error_at_eof("Expected identifer after 'class', found EOF")),
+            };
+
+            match &next_token.token_type {
+                TokenType::Identifier(identifier) => (identifier, next_token.line),
+                _ => {
+                    return Err(error_at_token(
+                        next_token,
+                        &format!(
+                            "Expected identifier after 'class', found {}",
+                            next_token.lexeme()
+                        ),
+                    ))
+                }
+            }
+        };
+
+        let mut superclass = None;
+        if self.match_tokens(&[TokenType::Less]).is_some() {
+            let next_token = match self.advance() {
+                Some(t) => t,
+                None => {
+                    return Err(error_at_eof(
+                        "Expected superclass name after '<', found EOF",
+                    ))
+                }
+            };
+
+            match &next_token.token_type {
+                TokenType::Identifier(identifier) => {
+                    superclass = Some(expr::Variable::new(identifier, next_token.line));
+                }
+                _ => {
+                    return Err(error_at_token(
+                        next_token,
+                        &format!(
+                            "Expected superclass name after '<', found {}",
+                            next_token.lexeme()
+                        ),
+                    ))
+                }
+            }
+        }
+
+        self.consume_token(TokenType::LeftBrace, "Expected '{' after class name")?;
+
+        let mut methods = vec![];
         while self.match_tokens(&[TokenType::RightBrace]).is_none() {
-            statements.push(self.statement()?);
+            if let stmt::StmtKind::Function(method) = self.function_stmt()?.kind {
+                methods.push(method);
+            }
         }
 
-        Ok(stmt::new_function(
-            name.clone(),
-            parameters,
-            statements,
-            line,
-        ))
+        Ok(stmt::new_class(name, line, superclass, methods))
     }
 
-    fn return_stmt(&mut self) -> StmtResult {
+    fn return_stmt(&mut self, line: u32) -> StmtResult {
+        if self.match_tokens(&[TokenType::SemiColon]).is_some() {
+            return Ok(stmt::new_return(None, line));
+        }
+
         let expr = self.expression()?;
         self.consume_token(TokenType::SemiColon, "Expected ';' after expression")?;
-        Ok(stmt::new_return(expr))
+        Ok(stmt::new_return(Some(expr), line))
     }
 
     fn expr_stmt(&mut self) -> StmtResult {
@@ -216,11 +506,7 @@ impl<'a> Parser<'a> {
     }
 
     fn block_stmt(&mut self) -> StmtResult {
-        let mut statements = vec![];
-
-        while self.match_tokens(&[TokenType::RightBrace]).is_none() {
-            statements.push(self.statement()?);
-        }
+        let statements = self.block_statements()?;
 
         Ok(stmt::new_block(statements))
     }
@@ -239,21 +525,20 @@ impl<'a> Parser<'a> {
 
     fn var_stmt(&mut self) -> StmtResult {
         let (identifier_name, line) = {
-            let token = match self.iter.next() {
+            let token = match self.advance() {
                 Some(t) => t,
-                None => return Err(vec![format!("Expected identifier after 'var'")]),
+                None => return Err(error_at_eof("Expected identifier after 'var'")),
             };
 
             match &token.token_type {
                 TokenType::Identifier(name) => (name, token.line),
-                _ => return Err(vec![format!("Expected identifier after 'var'")]),
+                _ => return Err(error_at_token(token, "Expected identifier after 'var'")),
             }
         };
 
         let initializer = if self.match_tokens(&[TokenType::Equal]).is_some() {
             Some(self.expression()?)
-        }
-        else {
+        } else {
             None
         };
 
@@ -262,19 +547,59 @@ impl<'a> Parser<'a> {
         Ok(stmt::new_var(identifier_name, line, initializer))
     }
 
+    fn loop_control_stmt(&mut self, token: &Token) -> StmtResult {
+        if self.loop_depth == 0 {
+            let mut hints = vec![];
+            if self.in_function_in_loop {
+                hints.push(Hint::Note(
+                    "loops do not reach into the bodies of functions declared inside them"
+                        .to_string(),
+                ));
+            }
+
+            return Err(vec![LoxError::Parse(Box::new(ErrorDetail {
+                hints,
+                ..ErrorDetail::at_token(
+                    ErrorKind::LoopControlOutsideLoop,
+                    format!("Can't use '{}' outside of a loop", token.lexeme()),
+                    token,
+                )
+            }))]);
+        }
+
+        self.consume_token(
+            TokenType::SemiColon,
+            &format!("Expected ';' after '{}'", token.lexeme()),
+        )?;
+
+        match token.token_type {
+            TokenType::Break => Ok(stmt::new_break()),
+            _ => Ok(stmt::new_continue()),
+        }
+    }
+
+    fn loop_body(&mut self) -> StmtResult {
+        self.loop_depth += 1;
+        let body = self.statement();
+        self.loop_depth -= 1;
+
+        body
+    }
+
     fn while_stmt(&mut self) -> StmtResult {
         self.consume_token(TokenType::LeftParen, "Expected '(' after while statement")?;
         let condition = self.expression()?;
         self.consume_token(TokenType::RightParen, "Expected ')' after while statement")?;
 
-        let body = self.statement()?;
+        let body = self.loop_body()?;
 
         Ok(stmt::new_while(condition, body))
     }
 
     fn for_stmt(&mut self) -> StmtResult {
+        let start = self.previous.map_or(0, |token| token.span.start);
         let mut initializer = None;
-        let mut condition = expr::Expr::Bool(true);
+        let mut condition = expr::new_bool(true);
         let mut loop_eval = None;
 
         self.consume_token(TokenType::LeftParen, "Expected '(' after for statement")?;
@@ -293,14 +618,13 @@ impl<'a> Parser<'a> {
             self.consume_token(TokenType::RightParen, "Expected ')' after for loop expr")?;
         }
 
-        let mut body = self.statement()?;
-
-        if let Some(le) = loop_eval {
-            let inner_body = body;
-            body = stmt::new_block(vec![inner_body, stmt::new_expr(le)]);
-        }
+        let body = self.loop_body()?;
 
-        let while_stmt = stmt::new_while(condition, body);
+        let mut while_stmt = match loop_eval {
+            None => stmt::new_while(condition, body),
+            Some(le) => stmt::new_while_with_increment(condition, body, le),
+        };
+        while_stmt.span = self.span_from(start);
         let final_stmt = match initializer {
             None => while_stmt,
             Some(i) => stmt::new_block(vec![i, while_stmt]),
@@ -310,51 +634,89 @@ impl<'a> Parser<'a> {
     }
 
     fn expression(&mut self) -> ExprResult {
+        self.check_nesting()?;
         return self.assignment();
     }
 
     fn assignment(&mut self) -> ExprResult {
+        let start = self.start();
         let mut expr = self.logical_or()?;
 
-        if self.match_tokens(&[TokenType::Equal]).is_some() {
-            match &expr {
-                expr::Expr::Variable(variable) => {
-                    expr = expr::new_assignment(&variable.name, variable.line, self.expression()?);
+        if let Some(equals) = self.match_tokens(&[TokenType::Equal]) {
+            let target_span = expr.span;
+            match expr.kind {
+                expr::ExprKind::Variable(variable) => {
+                    expr = expr::new_assignment(
+                        &variable.name.name(),
+                        variable.line,
+                        self.expression()?,
+                    );
+                }
+                expr::ExprKind::Get(get) => {
+                    expr = expr::new_set(*get.object, &get.name, get.line, self.expression()?);
+                }
+                expr::ExprKind::Index(index) => {
+                    expr = expr::new_index_set(
+                        *index.object,
+                        *index.index,
+                        index.line,
+                        self.expression()?,
+                    );
+                }
+                _ => {
+                    return Err(vec![LoxError::Parse(Box::new(ErrorDetail {
+                        span: Some(target_span),
+                        hints: vec![Hint::Help(
+                            "only variables, properties and list or map elements can be assigned to"
+                                .to_string(),
+                        )],
+                        ..ErrorDetail::at_line(
+                            ErrorKind::InvalidAssignmentTarget,
+                            "Invalid assignment target".to_string(),
+                            equals.line,
+                        )
+                    }))])
                 }
-                _ => return Err(vec![format!("Invalid assignment target")]),
             }
+            expr = self.finish_expr(start, expr);
         }
 
         Ok(expr)
     }
 
     fn logical_or(&mut self) -> ExprResult {
-        let expr = self.logical_and()?;
+        let start = self.start();
+        let mut expr = self.logical_and()?;
 
-        if let Some(operator) = self.match_tokens(&[TokenType::Or]) {
-            return Ok(expr::new_binary(expr, operator, self.comparison()?));
+        while let Some(operator) = self.match_tokens(&[TokenType::Or]) {
+            let logical = expr::new_logical(expr, operator, self.logical_and()?);
+            expr = self.finish_expr(start, logical);
         }
 
         Ok(expr)
     }
 
     fn logical_and(&mut self) -> ExprResult {
-        let expr = self.equality()?;
+        let start = self.start();
+        let mut expr = self.equality()?;
 
-        if let Some(operator) = self.match_tokens(&[TokenType::And]) {
-            return Ok(expr::new_binary(expr, operator, self.comparison()?));
+        while let Some(operator) = self.match_tokens(&[TokenType::And]) {
+            let logical = expr::new_logical(expr, operator, self.equality()?);
+            expr = self.finish_expr(start, logical);
         }
 
         Ok(expr)
     }
 
     fn equality(&mut self) -> ExprResult {
+        let start = self.start();
         let expr = self.comparison()?;
 
         if let Some(operator) = self.match_tokens(&[TokenType::EqualEqual, TokenType::BangEqual]) {
             match operator.token_type {
                 TokenType::EqualEqual | TokenType::BangEqual => {
-                    return Ok(expr::new_binary(expr, operator, self.comparison()?));
+                    let binary = expr::new_binary(expr, operator, self.comparison()?);
+                    return Ok(self.finish_expr(start, binary));
                 }
                 _ => panic!("Unexpected token parsing comparison: {:?}", operator),
             }
@@ -364,6 +726,7 @@ impl<'a> Parser<'a> {
     }
 
     fn comparison(&mut self) -> ExprResult {
+        let start = self.start();
         let expr = self.term()?;
 
         if let Some(operator) = self.match_tokens(&[
@@ -377,7 +740,8 @@ impl<'a> Parser<'a> {
                 | TokenType::LessEqual
                 | TokenType::Greater
                 | TokenType::GreaterEqual => {
-                    return Ok(expr::new_binary(expr, operator, self.term()?))
+                    let binary = expr::new_binary(expr, operator, self.term()?);
+                    return Ok(self.finish_expr(start, binary));
                 }
                 _ => panic!("Unexpected token parsing comparison: {:?}", operator),
             }
@@ -387,39 +751,48 @@ impl<'a> Parser<'a> {
     }
 
     fn term(&mut self) -> ExprResult {
+        let start = self.start();
         let mut expr = self.factor()?;
 
         while let Some(token_type) = self.match_tokens(&[TokenType::Minus, TokenType::Plus]) {
             let binary = expr::new_binary(expr, token_type, self.factor()?);
 
-            expr = binary;
+            expr = self.finish_expr(start, binary);
         }
 
         return Ok(expr);
     }
 
     fn factor(&mut self) -> ExprResult {
+        let start = self.start();
         let mut expr = self.unary()?;
 
-        while let Some(token_type) = self.match_tokens(&[TokenType::Slash, TokenType::Star]) {
+        while let Some(token_type) = self.match_tokens(&[
+            TokenType::Slash,
+            TokenType::Star,
+            TokenType::Percent,
+            TokenType::TildeSlash,
+        ]) {
             let binary = expr::new_binary(expr, token_type, self.unary()?);
 
-            expr = binary;
+            expr = self.finish_expr(start, binary);
         }
 
         return Ok(expr);
     }
 
     fn unary(&mut self) -> ExprResult {
+        self.check_nesting()?;
+        let start = self.start();
         if let Some(t) = self.match_tokens(&[TokenType::Bang, TokenType::Minus]) {
             match t.token_type {
                 TokenType::Bang => {
                     let expr = self.unary()?;
-                    return Ok(expr::new_logical_not(expr));
+                    return Ok(self.finish_expr(start, expr::new_logical_not(expr)));
                 }
                 TokenType::Minus => {
                     let expr = self.unary()?;
-                    return Ok(expr::new_unary_negate(expr));
+                    return Ok(self.finish_expr(start, expr::new_unary_negate(expr, t.line)));
                 }
                 _ => panic!("Unexpected token parsing unary: {:?}", t),
             }
@@ -429,25 +802,58 @@ impl<'a> Parser<'a> {
     }
 
     fn call(&mut self) -> ExprResult {
-        let expr = self.primary()?;
-
-        if let Some(left_param) = self.match_tokens(&[TokenType::LeftParen]) {
-            let mut args = vec![];
-            if self.match_tokens(&[TokenType::RightParen]).is_none() {
-                loop {
-                    args.push(Box::new(self.expression()?));
-                    if self.match_tokens(&[TokenType::Comma]).is_none() {
-                        break;
+        let start = self.start();
+        let primary = self.primary()?;
+        let mut expr = self.finish_expr(start, primary);
+
+        loop {
+            if let Some(left_param) = self.match_tokens(&[TokenType::LeftParen]) {
+                let mut args = vec![];
+                if self.match_tokens(&[TokenType::RightParen]).is_none() {
+                    loop {
+                        args.push(self.expression()?);
+                        if self.match_tokens(&[TokenType::Comma]).is_none() {
+                            break;
+                        }
                     }
+
+                    self.consume_token(
+                        TokenType::RightParen,
+                        "Expected ')' after function call arguments",
+                    )?;
                 }
 
-                self.consume_token(
-                    TokenType::RightParen,
-                    "Expected ')' after function call arguments",
-                )?;
-            }
+                expr = self.finish_expr(start, expr::new_call(expr, left_param.line, args));
+            } else if let Some(left_bracket) = self.match_tokens(&[TokenType::LeftBracket]) {
+                let index = self.expression()?;
+                self.consume_token(TokenType::RightBracket, "Expected ']' after index")?;
+
+                expr = self.finish_expr(start, expr::new_index(expr, index, left_bracket.line));
+            } else if self.match_tokens(&[TokenType::Dot]).is_some() {
+                let next_token = match self.advance() {
+                    Some(t) => t,
+                    None => {
+                        return Err(error_at_eof("Expected property name after '.', found EOF"))
+                    }
+                };
 
-            return Ok(expr::new_call(expr, left_param.line, args));
+                match &next_token.token_type {
+                    TokenType::Identifier(name) => {
+                        expr = self.finish_expr(start, expr::new_get(expr, name, next_token.line));
+                    }
+                    _ => {
+                        return Err(error_at_token(
+                            next_token,
+                            &format!(
+                                "Expected property name after '.', found {}",
+                                next_token.lexeme()
+                            ),
+                        ))
+                    }
+                }
+            } else {
+                break;
+            }
         }
 
         return Ok(expr);
@@ -455,57 +861,144 @@ impl<'a> Parser<'a> {
 
     fn grouping(&mut self) -> ExprResult {
         let expr = self.expression()?;
-        if let Some(t) = self.iter.next() {
+        if let Some(t) = self.advance() {
             if t.token_type == TokenType::RightParen {
                 return Ok(expr::new_grouping(expr));
             } else {
-                return Err(vec![format!(
-                    "Expected ')' but found {} at line {}",
-                    t.to_string(),
-                    t.line
-                )]);
+                return Err(error_at_token(
+                    t,
+                    &format!("Expected ')' but found {}", t.lexeme()),
+                ));
             }
         } else {
-            return Err(vec![format!("Expected ')' but found EOF")]);
+            return Err(error_at_eof("Expected ')' but found EOF"));
         }
     }
 
+    fn list(&mut self) -> ExprResult {
+        let mut elements = vec![];
+        if self.match_tokens(&[TokenType::RightBracket]).is_none() {
+            loop {
+                elements.push(self.expression()?);
+                if self.match_tokens(&[TokenType::Comma]).is_none() {
+                    break;
+                }
+            }
+
+            self.consume_token(TokenType::RightBracket, "Expected ']' after list elements")?;
+        }
+
+        Ok(expr::new_list(elements))
+    }
+
+    fn map(&mut self, line: u32) -> ExprResult {
+        let mut entries = vec![];
+        if self.match_tokens(&[TokenType::RightBrace]).is_none() {
+            loop {
+                let key = self.expression()?;
+                self.consume_token(TokenType::Colon, "Expected ':' after map key")?;
+                let value = self.expression()?;
+                entries.push((key, value));
+
+                if self.match_tokens(&[TokenType::Comma]).is_none() {
+                    break;
+                }
+            }
+
+            self.consume_token(TokenType::RightBrace, "Expected '}' after map entries")?;
+        }
+
+        Ok(expr::new_map(entries, line))
+    }
+
     fn identifier(&mut self, name: &str, line: u32) -> ExprResult {
         Ok(expr::new_variable(name, line))
     }
 
+    fn lambda(&mut self, line: u32) -> ExprResult {
+        self.consume_token(TokenType::LeftParen, "Expected '(' after 'fun'")?;
+
+        let (parameters, statements) = self.function_body()?;
+
+        Ok(expr::new_lambda(parameters, statements, line))
+    }
+
+    fn super_expr(&mut self, line: u32) -> ExprResult {
+        self.consume_token(TokenType::Dot, "Expected '.' after 'super'")?;
+
+        let next_token = match self.advance() {
+            Some(t) => t,
+            None => return Err(error_at_eof("Expected superclass method name, found EOF")),
+        };
+
+        match &next_token.token_type {
+            TokenType::Identifier(method) => Ok(expr::new_super(method, line)),
+            _ => Err(error_at_token(
+                next_token,
+                &format!(
+                    "Expected superclass method name, found {}",
+                    next_token.lexeme()
+                ),
+            )),
+        }
+    }
+
     fn primary(&mut self) -> ExprResult {
-        if let Some(t) = self.iter.next() {
+        if let Some(t) = self.advance() {
             match &t.token_type {
-                TokenType::True => return Ok(expr::Expr::Bool(true)),
-                TokenType::False => return Ok(expr::Expr::Bool(false)),
+                TokenType::True => return Ok(expr::new_bool(true)),
+                TokenType::False => return Ok(expr::new_bool(false)),
 
-                TokenType::Nil => return Ok(expr::Expr::Nil),
+                TokenType::Nil => return Ok(expr::new_nil()),
+                TokenType::This => return Ok(expr::new_this(t.line)),
+                TokenType::Super => return self.super_expr(t.line),
 
-                TokenType::Number(value) => return Ok(expr::Expr::Number(*value)),
-                TokenType::Str(value) => return Ok(expr::Expr::Str(value.clone())),
+                TokenType::Number(value) => return Ok(expr::new_number(*value)),
+                TokenType::Int(value) => return Ok(expr::new_int(*value)),
+                TokenType::Str(value) => return Ok(expr::new_str(value)),
 
                 TokenType::LeftParen => return self.grouping(),
+                TokenType::LeftBracket => return self.list(),
+                TokenType::LeftBrace => return self.map(t.line),
+
+                TokenType::Fun => return self.lambda(t.line),
 
-                TokenType::Identifier(name) => return self.identifier(&name, t.line),
+                TokenType::Identifier(name) => return self.identifier(name, t.line),
 
                 _ => {
-                    return Err(vec![format!(
-                        "Expected primary expression, found {} at line {}",
-                        t.to_string(),
-                        t.line
-                    )])
+                    return Err(error_at_token(
+                        t,
+                        &format!("Expected primary expression, found {}", t.lexeme()),
+                    ))
                 }
             };
         }
 
-        Err(vec!["Expected primary expression, found EOF".to_owned()])
+        Err(error_at_eof("Expected primary expression, found EOF"))
     }
 }
 
+fn error(kind: ErrorKind, message: String) -> Vec<LoxError> {
+    vec![LoxError::Parse(Box::new(ErrorDetail::new(kind, message)))]
+}
+
+fn error_at_eof(message: &str) -> Vec<LoxError> {
+    error(ErrorKind::UnexpectedEof, message.to_string())
+}
+
+fn error_at_token(token: &Token, message: &str) -> Vec<LoxError> {
+    vec![LoxError::Parse(Box::new(ErrorDetail::at_token(
+        ErrorKind::UnexpectedToken,
+        message.to_string(),
+        token,
+    )))]
+}
+
 #[cfg(test)]
+#[allow(clippy::approx_constant, clippy::useless_vec)]
 mod test {
     use super::*;
+    use std::rc::Rc;
 
     #[test]
     fn primary() {
@@ -515,7 +1008,7 @@ mod test {
                 Token::new(TokenType::SemiColon, 1)
             ])
             .unwrap(),
-            vec![stmt::new_expr(expr::Expr::Bool(true))]
+            vec![stmt::new_expr(expr::new_bool(true))]
         );
         assert_eq!(
             parse(&vec![
@@ -523,7 +1016,7 @@ mod test {
                 Token::new(TokenType::SemiColon, 1)
             ])
             .unwrap(),
-            vec![stmt::new_expr(expr::Expr::Bool(false))]
+            vec![stmt::new_expr(expr::new_bool(false))]
         );
         assert_eq!(
             parse(&vec![
@@ -531,7 +1024,7 @@ mod test {
                 Token::new(TokenType::SemiColon, 1)
             ])
             .unwrap(),
-            vec![stmt::new_expr(expr::Expr::Nil)]
+            vec![stmt::new_expr(expr::new_nil())]
         );
         assert_eq!(
             parse(&vec![
@@ -539,7 +1032,7 @@ mod test {
                 Token::new(TokenType::SemiColon, 1)
             ])
             .unwrap(),
-            vec![stmt::new_expr(expr::Expr::Number(3.142))]
+            vec![stmt::new_expr(expr::new_number(3.142))]
         );
         assert_eq!(
             parse(&vec![
@@ -547,7 +1040,7 @@ mod test {
                 Token::new(TokenType::SemiColon, 1)
             ])
             .unwrap(),
-            vec![stmt::new_expr(expr::Expr::Str("Hello World".to_owned()))]
+            vec![stmt::new_expr(expr::new_str("Hello World"))]
         );
     }
 
@@ -562,9 +1055,9 @@ mod test {
             ])
             .unwrap(),
             vec![stmt::new_expr(expr::new_binary(
-                expr::Expr::Number(4.0),
+                expr::new_number(4.0),
                 Token::new(TokenType::Plus, 1),
-                expr::Expr::Number(8.5)
+                expr::new_number(8.5)
             ))]
         );
 
@@ -577,9 +1070,9 @@ mod test {
             ])
             .unwrap(),
             vec![stmt::new_expr(expr::new_binary(
-                expr::Expr::Number(4.0),
+                expr::new_number(4.0),
                 Token::new(TokenType::Minus, 1),
-                expr::Expr::Number(8.5)
+                expr::new_number(8.5)
             ))]
         );
     }
@@ -595,9 +1088,9 @@ mod test {
             ])
             .unwrap(),
             vec![stmt::new_expr(expr::new_binary(
-                expr::Expr::Number(4.0),
+                expr::new_number(4.0),
                 Token::new(TokenType::Slash, 1),
-                expr::Expr::Number(8.5)
+                expr::new_number(8.5)
             ))]
         );
 
@@ -610,9 +1103,9 @@ mod test {
             ])
             .unwrap(),
             vec![stmt::new_expr(expr::new_binary(
-                expr::Expr::Number(4.0),
+                expr::new_number(4.0),
                 Token::new(TokenType::Star, 1),
-                expr::Expr::Number(8.5)
+                expr::new_number(8.5)
             ))]
         );
     }
@@ -635,19 +1128,19 @@ mod test {
             .unwrap(),
             vec![stmt::new_expr(expr::new_binary(
                 expr::new_binary(
-                    expr::Expr::Number(1.0),
+                    expr::new_number(1.0),
                     Token::new(TokenType::Plus, 1),
                     expr::new_binary(
-                        expr::Expr::Number(2.0),
+                        expr::new_number(2.0),
                         Token::new(TokenType::Slash, 1),
-                        expr::Expr::Number(3.0)
+                        expr::new_number(3.0)
                     )
                 ),
                 Token::new(TokenType::Minus, 1),
                 expr::new_binary(
-                    expr::Expr::Number(4.0),
+                    expr::new_number(4.0),
                     Token::new(TokenType::Star, 1),
-                    expr::Expr::Number(5.0)
+                    expr::new_number(5.0)
                 )
             ))]
         );
@@ -689,15 +1182,15 @@ mod test {
             .unwrap(),
             vec![stmt::new_expr(expr::new_binary(
                 expr::new_grouping(expr::new_binary(
-                    expr::Expr::Number(1.0),
+                    expr::new_number(1.0),
                     Token::new(TokenType::Plus, 1),
-                    expr::Expr::Number(2.0)
+                    expr::new_number(2.0)
                 )),
                 Token::new(TokenType::Star, 1),
                 expr::new_grouping(expr::new_binary(
-                    expr::Expr::Number(3.0),
+                    expr::new_number(3.0),
                     Token::new(TokenType::Minus, 1),
-                    expr::Expr::Number(4.0)
+                    expr::new_number(4.0)
                 ))
             ))]
         );
@@ -725,9 +1218,7 @@ mod test {
                 Token::new(TokenType::SemiColon, 1),
             ])
             .unwrap(),
-            vec![stmt::new_expr(expr::new_logical_not(expr::Expr::Bool(
-                true
-            )))]
+            vec![stmt::new_expr(expr::new_logical_not(expr::new_bool(true)))]
         );
 
         assert_eq!(
@@ -743,9 +1234,9 @@ mod test {
             .unwrap(),
             vec![stmt::new_expr(expr::new_logical_not(expr::new_grouping(
                 expr::new_binary(
-                    expr::Expr::Number(2.0),
+                    expr::new_number(2.0),
                     Token::new(TokenType::Plus, 1),
-                    expr::Expr::Number(5.0)
+                    expr::new_number(5.0)
                 )
             )))]
         )
@@ -760,9 +1251,10 @@ mod test {
                 Token::new(TokenType::SemiColon, 1),
             ])
             .unwrap(),
-            vec![stmt::new_expr(expr::new_unary_negate(expr::Expr::Number(
-                2.0
-            )))]
+            vec![stmt::new_expr(expr::new_unary_negate(
+                expr::new_number(2.0),
+                1
+            ))]
         );
 
         assert!(parse(&vec![Token::new(TokenType::Minus, 1)]).is_err());
@@ -779,9 +1271,9 @@ mod test {
             ])
             .unwrap(),
             vec![stmt::new_expr(expr::new_binary(
-                expr::Expr::Number(2.0),
+                expr::new_number(2.0),
                 Token::new(TokenType::Less, 1),
-                expr::Expr::Number(3.0)
+                expr::new_number(3.0)
             ))]
         );
 
@@ -794,9 +1286,9 @@ mod test {
             ])
             .unwrap(),
             vec![stmt::new_expr(expr::new_binary(
-                expr::Expr::Number(2.0),
+                expr::new_number(2.0),
                 Token::new(TokenType::LessEqual, 1),
-                expr::Expr::Number(3.0)
+                expr::new_number(3.0)
             ))]
         );
 
@@ -809,9 +1301,9 @@ mod test {
             ])
             .unwrap(),
             vec![stmt::new_expr(expr::new_binary(
-                expr::Expr::Number(2.0),
+                expr::new_number(2.0),
                 Token::new(TokenType::Greater, 1),
-                expr::Expr::Number(3.0)
+                expr::new_number(3.0)
             ))]
         );
 
@@ -824,9 +1316,9 @@ mod test {
             ])
             .unwrap(),
             vec![stmt::new_expr(expr::new_binary(
-                expr::Expr::Number(2.0),
+                expr::new_number(2.0),
                 Token::new(TokenType::GreaterEqual, 1),
-                expr::Expr::Number(3.0)
+                expr::new_number(3.0)
             ))]
         );
     }
@@ -842,9 +1334,9 @@ mod test {
             ])
             .unwrap(),
             vec![stmt::new_expr(expr::new_binary(
-                expr::Expr::Number(3.0),
+                expr::new_number(3.0),
                 Token::new(TokenType::EqualEqual, 1),
-                expr::Expr::Number(3.0)
+                expr::new_number(3.0)
             ))]
         );
 
@@ -857,9 +1349,9 @@ mod test {
             ])
             .unwrap(),
             vec![stmt::new_expr(expr::new_binary(
-                expr::Expr::Number(2.0),
+                expr::new_number(2.0),
                 Token::new(TokenType::BangEqual, 1),
-                expr::Expr::Number(3.0)
+                expr::new_number(3.0)
             ))]
         );
     }
@@ -874,10 +1366,10 @@ mod test {
                 Token::new(TokenType::SemiColon, 1),
             ])
             .unwrap(),
-            vec![stmt::new_expr(expr::new_binary(
-                expr::Expr::Number(3.0),
+            vec![stmt::new_expr(expr::new_logical(
+                expr::new_number(3.0),
                 Token::new(TokenType::Or, 1),
-                expr::Expr::Number(3.0)
+                expr::new_number(3.0)
             ))]
         );
     }
@@ -892,10 +1384,41 @@ mod test {
                 Token::new(TokenType::SemiColon, 1),
             ])
             .unwrap(),
-            vec![stmt::new_expr(expr::new_binary(
-                expr::Expr::Number(2.0),
+            vec![stmt::new_expr(expr::new_logical(
+                expr::new_number(2.0),
+                Token::new(TokenType::And, 1),
+                expr::new_number(3.0)
+            ))]
+        );
+    }
+
+    #[test]
+    fn logical_chain() {
+        // 'and' binds tighter than 'or', both group to the left
+        assert_eq!(
+            parse(&[
+                Token::new(TokenType::Number(1.0), 1),
+                Token::new(TokenType::Or, 1),
+                Token::new(TokenType::Number(2.0), 1),
                 Token::new(TokenType::And, 1),
-                expr::Expr::Number(3.0)
+                Token::new(TokenType::Number(3.0), 1),
+                Token::new(TokenType::Or, 1),
+                Token::new(TokenType::Number(4.0), 1),
+                Token::new(TokenType::SemiColon, 1),
+            ])
+            .unwrap(),
+            vec![stmt::new_expr(expr::new_logical(
+                expr::new_logical(
+                    expr::new_number(1.0),
+                    Token::new(TokenType::Or, 1),
+                    expr::new_logical(
+                        expr::new_number(2.0),
+                        Token::new(TokenType::And, 1),
+                        expr::new_number(3.0)
+                    )
+                ),
+                Token::new(TokenType::Or, 1),
+                expr::new_number(4.0)
             ))]
         );
     }
@@ -914,9 +1437,9 @@ mod test {
             ])
             .unwrap(),
             vec![stmt::new_print(vec![
-                expr::Expr::Number(3.0),
-                expr::Expr::Str("Hello, ".to_owned()),
-                expr::Expr::Str("World".to_owned())
+                expr::new_number(3.0),
+                expr::new_str("Hello, "),
+                expr::new_str("World")
             ])]
         );
     }
@@ -935,8 +1458,8 @@ mod test {
             ])
             .unwrap(),
             vec![stmt::new_if(
-                expr::Expr::Bool(true),
-                stmt::new_print(vec![expr::Expr::Number(1.0)]),
+                expr::new_bool(true),
+                stmt::new_print(vec![expr::new_number(1.0)]),
                 None
             )]
         );
@@ -958,8 +1481,8 @@ mod test {
             ])
             .unwrap(),
             vec![stmt::new_if(
-                expr::Expr::Bool(true),
-                stmt::new_block(vec![stmt::new_print(vec![expr::Expr::Number(1.0)])]),
+                expr::new_bool(true),
+                stmt::new_block(vec![stmt::new_print(vec![expr::new_number(1.0)])]),
                 None
             )]
         );
@@ -983,9 +1506,9 @@ mod test {
             ])
             .unwrap(),
             vec![stmt::new_if(
-                expr::Expr::Bool(true),
-                stmt::new_print(vec![expr::Expr::Number(1.0)]),
-                Some(stmt::new_print(vec![expr::Expr::Number(2.0)]))
+                expr::new_bool(true),
+                stmt::new_print(vec![expr::new_number(1.0)]),
+                Some(stmt::new_print(vec![expr::new_number(2.0)]))
             )]
         );
     }
@@ -1012,10 +1535,10 @@ mod test {
             ])
             .unwrap(),
             vec![stmt::new_if(
-                expr::Expr::Bool(true),
-                stmt::new_block(vec![stmt::new_print(vec![expr::Expr::Number(1.0)])]),
+                expr::new_bool(true),
+                stmt::new_block(vec![stmt::new_print(vec![expr::new_number(1.0)])]),
                 Some(stmt::new_block(vec![stmt::new_print(vec![
-                    expr::Expr::Number(2.0)
+                    expr::new_number(2.0)
                 ])]))
             )]
         );
@@ -1032,7 +1555,7 @@ mod test {
                 Token::new(TokenType::SemiColon, 1),
             ])
             .unwrap(),
-            vec![stmt::new_var("variable", 1, expr::Expr::Number(10.0))]
+            vec![stmt::new_var("variable", 1, Some(expr::new_number(10.0)))]
         );
     }
 
@@ -1049,7 +1572,7 @@ mod test {
             vec![stmt::new_expr(expr::new_assignment(
                 "variable",
                 1,
-                expr::Expr::Number(10.0)
+                expr::new_number(10.0)
             ))]
         );
     }
@@ -1075,7 +1598,7 @@ mod test {
                 expr::new_binary(
                     expr::new_variable("counter", 1),
                     Token::new(TokenType::Less, 1),
-                    expr::Expr::Number(10.0)
+                    expr::new_number(10.0)
                 ),
                 stmt::new_block(vec![stmt::new_print(vec![expr::new_variable(
                     "counter", 1
@@ -1113,27 +1636,25 @@ mod test {
             ])
             .unwrap(),
             vec![stmt::new_block(vec![
-                stmt::new_var("counter", 1, expr::Expr::Number(0.0)),
-                stmt::new_while(
+                stmt::new_var("counter", 1, Some(expr::new_number(0.0))),
+                stmt::new_while_with_increment(
                     expr::new_binary(
                         expr::new_variable("counter", 1),
                         Token::new(TokenType::Less, 1),
-                        expr::Expr::Number(10.0)
+                        expr::new_number(10.0)
                     ),
-                    stmt::new_block(vec![
-                        stmt::new_block(vec![stmt::new_print(vec![expr::new_variable(
-                            "counter", 1
-                        )])]),
-                        stmt::new_expr(expr::new_assignment(
-                            "counter",
-                            1,
-                            expr::new_binary(
-                                expr::new_variable("counter", 1),
-                                Token::new(TokenType::Plus, 1),
-                                expr::Expr::Number(1.0)
-                            )
-                        ))
-                    ])
+                    stmt::new_block(vec![stmt::new_print(vec![expr::new_variable(
+                        "counter", 1
+                    )])]),
+                    expr::new_assignment(
+                        "counter",
+                        1,
+                        expr::new_binary(
+                            expr::new_variable("counter", 1),
+                            Token::new(TokenType::Plus, 1),
+                            expr::new_number(1.0)
+                        )
+                    )
                 )
             ])]
         );
@@ -1163,26 +1684,24 @@ mod test {
                 Token::new(TokenType::RightBrace, 1),
             ])
             .unwrap(),
-            vec![stmt::new_while(
+            vec![stmt::new_while_with_increment(
                 expr::new_binary(
                     expr::new_variable("counter", 1),
                     Token::new(TokenType::Less, 1),
-                    expr::Expr::Number(10.0)
+                    expr::new_number(10.0)
                 ),
-                stmt::new_block(vec![
-                    stmt::new_block(vec![stmt::new_print(vec![expr::new_variable(
-                        "counter", 1
-                    )])]),
-                    stmt::new_expr(expr::new_assignment(
-                        "counter",
-                        1,
-                        expr::new_binary(
-                            expr::new_variable("counter", 1),
-                            Token::new(TokenType::Plus, 1),
-                            expr::Expr::Number(1.0)
-                        )
-                    ))
-                ])
+                stmt::new_block(vec![stmt::new_print(vec![expr::new_variable(
+                    "counter", 1
+                )])]),
+                expr::new_assignment(
+                    "counter",
+                    1,
+                    expr::new_binary(
+                        expr::new_variable("counter", 1),
+                        Token::new(TokenType::Plus, 1),
+                        expr::new_number(1.0)
+                    )
+                )
             )]
         );
     }
@@ -1213,23 +1732,21 @@ mod test {
             ])
             .unwrap(),
             vec![stmt::new_block(vec![
-                stmt::new_var("counter", 1, expr::Expr::Number(0.0)),
-                stmt::new_while(
-                    expr::Expr::Bool(true),
-                    stmt::new_block(vec![
-                        stmt::new_block(vec![stmt::new_print(vec![expr::new_variable(
-                            "counter", 1
-                        )])]),
-                        stmt::new_expr(expr::new_assignment(
-                            "counter",
-                            1,
-                            expr::new_binary(
-                                expr::new_variable("counter", 1),
-                                Token::new(TokenType::Plus, 1),
-                                expr::Expr::Number(1.0)
-                            )
-                        ))
-                    ])
+                stmt::new_var("counter", 1, Some(expr::new_number(0.0))),
+                stmt::new_while_with_increment(
+                    expr::new_bool(true),
+                    stmt::new_block(vec![stmt::new_print(vec![expr::new_variable(
+                        "counter", 1
+                    )])]),
+                    expr::new_assignment(
+                        "counter",
+                        1,
+                        expr::new_binary(
+                            expr::new_variable("counter", 1),
+                            Token::new(TokenType::Plus, 1),
+                            expr::new_number(1.0)
+                        )
+                    )
                 )
             ])]
         );
@@ -1259,12 +1776,12 @@ mod test {
             ])
             .unwrap(),
             vec![stmt::new_block(vec![
-                stmt::new_var("counter", 1, expr::Expr::Number(0.0)),
+                stmt::new_var("counter", 1, Some(expr::new_number(0.0))),
                 stmt::new_while(
                     expr::new_binary(
                         expr::new_variable("counter", 1),
                         Token::new(TokenType::Less, 1),
-                        expr::Expr::Number(10.0)
+                        expr::new_number(10.0)
                     ),
                     stmt::new_block(vec![stmt::new_print(vec![expr::new_variable(
                         "counter", 1
@@ -1291,7 +1808,7 @@ mod test {
             ])
             .unwrap(),
             vec![stmt::new_while(
-                expr::Expr::Bool(true),
+                expr::new_bool(true),
                 stmt::new_block(vec![stmt::new_print(vec![expr::new_variable(
                     "counter", 1
                 )])])
@@ -1299,6 +1816,78 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_break_continue() {
+        assert_eq!(
+            parse(&[
+                Token::new(TokenType::While, 1),
+                Token::new(TokenType::LeftParen, 1),
+                Token::new(TokenType::True, 1),
+                Token::new(TokenType::RightParen, 1),
+                Token::new(TokenType::LeftBrace, 1),
+                Token::new(TokenType::Continue, 1),
+                Token::new(TokenType::SemiColon, 1),
+                Token::new(TokenType::Break, 1),
+                Token::new(TokenType::SemiColon, 1),
+                Token::new(TokenType::RightBrace, 1),
+            ])
+            .unwrap(),
+            vec![stmt::new_while(
+                expr::new_bool(true),
+                stmt::new_block(vec![stmt::new_continue(), stmt::new_break()])
+            )]
+        );
+    }
+
+    #[test]
+    fn test_break_outside_loop() {
+        let errors = parse(&[
+            Token::new(TokenType::Break, 1),
+            Token::new(TokenType::SemiColon, 1),
+        ])
+        .unwrap_err();
+        assert!(matches!(
+            &errors[0],
+            LoxError::Parse(detail) if detail.kind == ErrorKind::LoopControlOutsideLoop
+        ));
+        assert_eq!(
+            errors[0].to_string(),
+            "Line 1 at 'break': Can't use 'break' outside of a loop"
+        );
+        assert!(errors[0].detail().hints.is_empty());
+
+        // a function body doesn't inherit the enclosing loop
+        assert!(parse(&[
+            Token::new(TokenType::While, 1),
+            Token::new(TokenType::LeftParen, 1),
+            Token::new(TokenType::True, 1),
+            Token::new(TokenType::RightParen, 1),
+            Token::new(TokenType::Fun, 1),
+            Token::new(TokenType::Identifier("MyFunction".to_owned()), 1),
+            Token::new(TokenType::LeftParen, 1),
+            Token::new(TokenType::RightParen, 1),
+            Token::new(TokenType::LeftBrace, 1),
+            Token::new(TokenType::Continue, 1),
+            Token::new(TokenType::SemiColon, 1),
+            Token::new(TokenType::RightBrace, 1),
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_loop_control_in_function_in_loop() {
+        let tokens = crate::scanner::scan(
+            "while (true) { fun f() { { break; } } }
+fun g() { break; }",
+        )
+        .unwrap();
+        let errors = parse(&tokens).unwrap_err();
+
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].detail().hints.len(), 1);
+        assert!(errors[1].detail().hints.is_empty());
+    }
+
     #[test]
     fn test_call() {
         assert_eq!(
@@ -1318,9 +1907,9 @@ mod test {
                 expr::new_variable("MyFunction", 1),
                 1,
                 vec![
-                    Box::new(expr::Expr::Number(10.0)),
-                    Box::new(expr::Expr::Str("Arg".to_owned())),
-                    Box::new(expr::Expr::Bool(true))
+                    expr::new_number(10.0),
+                    expr::new_str("Arg"),
+                    expr::new_bool(true)
                 ]
             ))]
         );
@@ -1347,11 +1936,423 @@ mod test {
             ])
             .unwrap(),
             vec![stmt::new_function(
-                "MyFunction".to_owned(),
-                vec!["First".to_owned(), "Second".to_owned(), "Third".to_owned()],
+                "MyFunction",
+                vec![
+                    Symbol::intern("First"),
+                    Symbol::intern("Second"),
+                    Symbol::intern("Third")
+                ],
                 vec![stmt::new_print(vec![expr::new_variable("counter", 1)])],
                 1
             ),]
         );
     }
+
+    #[test]
+    fn test_chained_call() {
+        assert_eq!(
+            parse(&[
+                Token::new(TokenType::Identifier("MyFunction".to_owned()), 1),
+                Token::new(TokenType::LeftParen, 1),
+                Token::new(TokenType::Number(1.0), 1),
+                Token::new(TokenType::RightParen, 1),
+                Token::new(TokenType::LeftParen, 1),
+                Token::new(TokenType::RightParen, 1),
+                Token::new(TokenType::LeftParen, 2),
+                Token::new(TokenType::Number(2.0), 2),
+                Token::new(TokenType::RightParen, 2),
+                Token::new(TokenType::SemiColon, 2),
+            ])
+            .unwrap(),
+            vec![stmt::new_expr(expr::new_call(
+                expr::new_call(
+                    expr::new_call(
+                        expr::new_variable("MyFunction", 1),
+                        1,
+                        vec![expr::new_number(1.0)]
+                    ),
+                    1,
+                    vec![]
+                ),
+                2,
+                vec![expr::new_number(2.0)]
+            ))]
+        );
+    }
+
+    #[test]
+    fn test_lambda() {
+        assert_eq!(
+            parse(&[
+                Token::new(TokenType::Var, 1),
+                Token::new(TokenType::Identifier("add".to_owned()), 1),
+                Token::new(TokenType::Equal, 1),
+                Token::new(TokenType::Fun, 1),
+                Token::new(TokenType::LeftParen, 1),
+                Token::new(TokenType::Identifier("a".to_owned()), 1),
+                Token::new(TokenType::Comma, 1),
+                Token::new(TokenType::Identifier("b".to_owned()), 1),
+                Token::new(TokenType::RightParen, 1),
+                Token::new(TokenType::LeftBrace, 1),
+                Token::new(TokenType::Return, 1),
+                Token::new(TokenType::Identifier("a".to_owned()), 1),
+                Token::new(TokenType::SemiColon, 1),
+                Token::new(TokenType::RightBrace, 1),
+                Token::new(TokenType::SemiColon, 1),
+            ])
+            .unwrap(),
+            vec![stmt::new_var(
+                "add",
+                1,
+                Some(expr::new_lambda(
+                    vec![Symbol::intern("a"), Symbol::intern("b")],
+                    vec![stmt::new_return(Some(expr::new_variable("a", 1)), 1)],
+                    1
+                ))
+            )]
+        );
+    }
+
+    #[test]
+    fn test_lambda_statement() {
+        assert_eq!(
+            parse(&[
+                Token::new(TokenType::Fun, 1),
+                Token::new(TokenType::LeftParen, 1),
+                Token::new(TokenType::RightParen, 1),
+                Token::new(TokenType::LeftBrace, 1),
+                Token::new(TokenType::RightBrace, 1),
+                Token::new(TokenType::LeftParen, 1),
+                Token::new(TokenType::RightParen, 1),
+                Token::new(TokenType::SemiColon, 1),
+            ])
+            .unwrap(),
+            vec![stmt::new_expr(expr::new_call(
+                expr::new_lambda(vec![], vec![], 1),
+                1,
+                vec![]
+            ))]
+        );
+    }
+
+    #[test]
+    fn test_list() {
+        assert_eq!(
+            parse(&[
+                Token::new(TokenType::LeftBracket, 1),
+                Token::new(TokenType::Number(1.0), 1),
+                Token::new(TokenType::Comma, 1),
+                Token::new(TokenType::LeftBracket, 1),
+                Token::new(TokenType::RightBracket, 1),
+                Token::new(TokenType::RightBracket, 1),
+                Token::new(TokenType::SemiColon, 1),
+            ])
+            .unwrap(),
+            vec![stmt::new_expr(expr::new_list(vec![
+                expr::new_number(1.0),
+                expr::new_list(vec![])
+            ]))]
+        );
+    }
+
+    #[test]
+    fn test_map() {
+        assert_eq!(
+            parse(&[
+                Token::new(TokenType::Var, 1),
+                Token::new(TokenType::Identifier("map".to_owned()), 1),
+                Token::new(TokenType::Equal, 1),
+                Token::new(TokenType::LeftBrace, 1),
+                Token::new(TokenType::Str("key".to_owned()), 1),
+                Token::new(TokenType::Colon, 1),
+                Token::new(TokenType::Number(1.0), 1),
+                Token::new(TokenType::Comma, 1),
+                Token::new(TokenType::Number(2.0), 1),
+                Token::new(TokenType::Colon, 1),
+                Token::new(TokenType::LeftBrace, 1),
+                Token::new(TokenType::RightBrace, 1),
+                Token::new(TokenType::RightBrace, 1),
+                Token::new(TokenType::SemiColon, 1),
+            ])
+            .unwrap(),
+            vec![stmt::new_var(
+                "map",
+                1,
+                Some(expr::new_map(
+                    vec![
+                        (expr::new_str("key"), expr::new_number(1.0)),
+                        (expr::new_number(2.0), expr::new_map(vec![], 1))
+                    ],
+                    1
+                ))
+            )]
+        );
+    }
+
+    #[test]
+    fn test_map_statement() {
+        assert_eq!(
+            parse(&[
+                Token::new(TokenType::LeftBrace, 1),
+                Token::new(TokenType::Identifier("key".to_owned()), 1),
+                Token::new(TokenType::Colon, 1),
+                Token::new(TokenType::Nil, 1),
+                Token::new(TokenType::RightBrace, 1),
+                Token::new(TokenType::SemiColon, 1),
+                Token::new(TokenType::LeftBrace, 2),
+                Token::new(TokenType::Identifier("key".to_owned()), 2),
+                Token::new(TokenType::SemiColon, 2),
+                Token::new(TokenType::RightBrace, 2),
+            ])
+            .unwrap(),
+            vec![
+                stmt::new_expr(expr::new_map(
+                    vec![(expr::new_variable("key", 1), expr::new_nil())],
+                    1
+                )),
+                stmt::new_block(vec![stmt::new_expr(expr::new_variable("key", 2))])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_index() {
+        assert_eq!(
+            parse(&[
+                Token::new(TokenType::Identifier("list".to_owned()), 1),
+                Token::new(TokenType::LeftBracket, 1),
+                Token::new(TokenType::Number(0.0), 1),
+                Token::new(TokenType::RightBracket, 1),
+                Token::new(TokenType::LeftBracket, 1),
+                Token::new(TokenType::Number(1.0), 1),
+                Token::new(TokenType::RightBracket, 1),
+                Token::new(TokenType::Equal, 1),
+                Token::new(TokenType::Identifier("list".to_owned()), 1),
+                Token::new(TokenType::LeftBracket, 1),
+                Token::new(TokenType::Number(2.0), 1),
+                Token::new(TokenType::RightBracket, 1),
+                Token::new(TokenType::SemiColon, 1),
+            ])
+            .unwrap(),
+            vec![stmt::new_expr(expr::new_index_set(
+                expr::new_index(expr::new_variable("list", 1), expr::new_number(0.0), 1),
+                expr::new_number(1.0),
+                1,
+                expr::new_index(expr::new_variable("list", 1), expr::new_number(2.0), 1)
+            ))]
+        );
+    }
+
+    #[test]
+    fn test_class() {
+        assert_eq!(
+            parse(&[
+                Token::new(TokenType::Class, 1),
+                Token::new(TokenType::Identifier("MyClass".to_owned()), 1),
+                Token::new(TokenType::LeftBrace, 1),
+                Token::new(TokenType::Identifier("method".to_owned()), 2),
+                Token::new(TokenType::LeftParen, 2),
+                Token::new(TokenType::RightParen, 2),
+                Token::new(TokenType::LeftBrace, 2),
+                Token::new(TokenType::Return, 2),
+                Token::new(TokenType::This, 2),
+                Token::new(TokenType::SemiColon, 2),
+                Token::new(TokenType::RightBrace, 2),
+                Token::new(TokenType::RightBrace, 3),
+            ])
+            .unwrap(),
+            vec![stmt::new_class(
+                "MyClass",
+                1,
+                None,
+                vec![Rc::new(stmt::Function {
+                    name: Symbol::intern("method"),
+                    parameters: vec![],
+                    statements: vec![stmt::new_return(Some(expr::new_this(2)), 2)],
+                    line: 2,
+                })]
+            )]
+        );
+    }
+
+    #[test]
+    fn test_subclass() {
+        assert_eq!(
+            parse(&[
+                Token::new(TokenType::Class, 1),
+                Token::new(TokenType::Identifier("Derived".to_owned()), 1),
+                Token::new(TokenType::Less, 1),
+                Token::new(TokenType::Identifier("Base".to_owned()), 1),
+                Token::new(TokenType::LeftBrace, 1),
+                Token::new(TokenType::Identifier("method".to_owned()), 2),
+                Token::new(TokenType::LeftParen, 2),
+                Token::new(TokenType::RightParen, 2),
+                Token::new(TokenType::LeftBrace, 2),
+                Token::new(TokenType::Return, 2),
+                Token::new(TokenType::Super, 2),
+                Token::new(TokenType::Dot, 2),
+                Token::new(TokenType::Identifier("method".to_owned()), 2),
+                Token::new(TokenType::LeftParen, 2),
+                Token::new(TokenType::RightParen, 2),
+                Token::new(TokenType::SemiColon, 2),
+                Token::new(TokenType::RightBrace, 2),
+                Token::new(TokenType::RightBrace, 3),
+            ])
+            .unwrap(),
+            vec![stmt::new_class(
+                "Derived",
+                1,
+                Some(expr::Variable::new("Base", 1)),
+                vec![Rc::new(stmt::Function {
+                    name: Symbol::intern("method"),
+                    parameters: vec![],
+                    statements: vec![stmt::new_return(
+                        Some(expr::new_call(expr::new_super("method", 2), 2, vec![])),
+                        2
+                    )],
+                    line: 2,
+                })]
+            )]
+        );
+    }
+
+    #[test]
+    fn test_super_without_method() {
+        assert!(parse(&[
+            Token::new(TokenType::Super, 1),
+            Token::new(TokenType::SemiColon, 1),
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_get_set() {
+        assert_eq!(
+            parse(&[
+                Token::new(TokenType::Identifier("object".to_owned()), 1),
+                Token::new(TokenType::Dot, 1),
+                Token::new(TokenType::Identifier("first".to_owned()), 1),
+                Token::new(TokenType::Dot, 1),
+                Token::new(TokenType::Identifier("second".to_owned()), 1),
+                Token::new(TokenType::Equal, 1),
+                Token::new(TokenType::Identifier("object".to_owned()), 1),
+                Token::new(TokenType::Dot, 1),
+                Token::new(TokenType::Identifier("method".to_owned()), 1),
+                Token::new(TokenType::LeftParen, 1),
+                Token::new(TokenType::RightParen, 1),
+                Token::new(TokenType::SemiColon, 1),
+            ])
+            .unwrap(),
+            vec![stmt::new_expr(expr::new_set(
+                expr::new_get(expr::new_variable("object", 1), "first", 1),
+                "second",
+                1,
+                expr::new_call(
+                    expr::new_get(expr::new_variable("object", 1), "method", 1),
+                    1,
+                    vec![]
+                )
+            ))]
+        );
+    }
+
+    #[test]
+    fn test_invalid_assignment_target() {
+        let errors = parse(&[
+            Token::new(TokenType::Identifier("object".to_owned()), 1),
+            Token::new(TokenType::LeftParen, 1),
+            Token::new(TokenType::RightParen, 1),
+            Token::new(TokenType::Equal, 1),
+            Token::new(TokenType::Number(1.0), 1),
+            Token::new(TokenType::SemiColon, 1),
+        ])
+        .unwrap_err();
+        assert_eq!(errors[0].detail().kind, ErrorKind::InvalidAssignmentTarget);
+    }
+
+    #[test]
+    fn test_spans() {
+        let tokens = crate::scanner::scan("print 1 + 22;\nx = f(2) [0];").unwrap();
+        let stmts = parse(&tokens).unwrap();
+
+        assert_eq!(stmts[0].span, Span { start: 0, end: 13 });
+        match &stmts[0].kind {
+            stmt::StmtKind::Print(print) => {
+                assert_eq!(print.exprs[0].span, Span { start: 6, end: 12 })
+            }
+            s => panic!("Expected print, found {:?}", s),
+        }
+
+        assert_eq!(stmts[1].span, Span { start: 14, end: 27 });
+        match &stmts[1].kind {
+            stmt::StmtKind::Expr(assignment) => {
+                assert_eq!(assignment.span, Span { start: 14, end: 26 });
+                match &assignment.kind {
+                    expr::ExprKind::Assignment(assignment) => {
+                        assert_eq!(assignment.expr.span, Span { start: 18, end: 26 })
+                    }
+                    e => panic!("Expected assignment, found {:?}", e),
+                }
+            }
+            s => panic!("Expected expression statement, found {:?}", s),
+        }
+    }
+
+    #[test]
+    fn test_error_recovery() {
+        let tokens = crate::scanner::scan(
+            "var a = ;\nprint a;\nfun f() {\n  print 1 +;\n  return 2;\n}\nbreak;\n1 = 2;",
+        )
+        .unwrap();
+        let errors = parse(&tokens).unwrap_err();
+
+        let lines: Vec<Option<u32>> = errors.iter().map(|e| e.detail().line).collect();
+        assert_eq!(lines, vec![Some(1), Some(4), Some(7), Some(8)]);
+        assert_eq!(errors[2].detail().kind, ErrorKind::LoopControlOutsideLoop);
+        assert_eq!(errors[3].detail().kind, ErrorKind::InvalidAssignmentTarget);
+    }
+
+    #[test]
+    fn test_error_locations() {
+        for source in [
+            "var = 3;",
+            "fun 1() {}",
+            "fun f(a, 2) {}",
+            "class 1 {}",
+            "class A < 1 {}",
+            "print a.1;",
+            "print (1 2;",
+            "print super.1;",
+            "print ;",
+        ] {
+            let tokens = crate::scanner::scan(source).unwrap();
+            let errors = parse(&tokens).unwrap_err();
+
+            let detail = errors[0].detail();
+            assert_eq!(detail.line, Some(1), "{}", source);
+            assert!(detail.span.is_some(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_too_deeply_nested() {
+        let source = format!("{}{}\nprint 1 +;", "{".repeat(100_000), "}".repeat(100_000));
+        let tokens = crate::scanner::scan(&source).unwrap();
+        let errors = parse(&tokens).unwrap_err();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].detail().kind, ErrorKind::StackOverflow);
+    }
+
+    #[test]
+    fn test_error_recovery_unclosed_block() {
+        let tokens = crate::scanner::scan("{ print 1;").unwrap();
+        let errors = parse(&tokens).unwrap_err();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "Expected '}' after block, found EOF at line 1"
+        );
+    }
 }
@@ -0,0 +1,345 @@
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::io::Write;
+use std::path::PathBuf;
+
+use rlox_tree_walker::{keywords, Lox, LoxError};
+
+const PROMPT: &str = ":> ";
+const CONTINUATION_PROMPT: &str = ".. ";
+const HISTORY_FILE: &str = ".lox_history";
+const COMMANDS: [&str; 5] = [":help", ":env", ":load", ":reset", ":quit"];
+
+const HELP: &str = "\
+Enter lox statements or expressions, the value of an expression is printed.
+Input continues on the next line while brackets are left open.
+
+Commands:
+  :help         print this message
+  :env          list the global variables
+  :load <file>  run a script in this session
+  :reset        forget everything defined so far
+  :quit         leave the prompt, as does end of input
+
+Tab completes keywords and global names, Ctrl-R searches the history.";
+
+#[derive(Debug, PartialEq)]
+enum Command<'a> {
+    Help,
+    Env,
+    Load(&'a str),
+    Reset,
+    Quit,
+}
+
+// Completes keywords, REPL commands and the names defined in the session
+struct LoxHelper {
+    globals: Vec<String>,
+}
+
+impl Completer for LoxHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = word_start(line, pos);
+        Ok((start, self.candidates(line, start, &line[start..pos])))
+    }
+}
+
+impl LoxHelper {
+    fn candidates(&self, line: &str, start: usize, word: &str) -> Vec<String> {
+        let mut candidates: Vec<String> = if start == 0 && word.starts_with(':') {
+            COMMANDS.iter().map(|command| command.to_string()).collect()
+        } else if word.is_empty() {
+            vec![]
+        } else if line.trim_start().starts_with(':') {
+            // command arguments are file names, not lox code
+            vec![]
+        } else {
+            keywords()
+                .map(|keyword| keyword.to_string())
+                .chain(self.globals.iter().cloned())
+                .collect()
+        };
+
+        candidates.retain(|candidate| candidate.starts_with(word));
+        candidates.sort();
+        candidates.dedup();
+        candidates
+    }
+}
+
+impl Hinter for LoxHelper {
+    type Hint = String;
+}
+
+impl Highlighter for LoxHelper {}
+
+impl Validator for LoxHelper {}
+
+impl Helper for LoxHelper {}
+
+// byte offset where the identifier or command ending at 'pos' starts
+fn word_start(line: &str, pos: usize) -> usize {
+    line[..pos]
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| c.is_alphanumeric() || *c == '_' || *c == ':')
+        .last()
+        .map_or(pos, |(i, _)| i)
+}
+
+fn history_path() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))?;
+    Some(PathBuf::from(home).join(HISTORY_FILE))
+}
+
+pub fn run_prompt() {
+    let mut editor: Editor<LoxHelper, DefaultHistory> = match Editor::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("Error: Failed to start the prompt: {}", e);
+            return;
+        }
+    };
+    editor.set_helper(Some(LoxHelper { globals: vec![] }));
+
+    let history_path = history_path();
+    if let Some(path) = &history_path {
+        // there's no history the first time the prompt is used
+        let _ = editor.load_history(path);
+    }
+
+    let mut lox = Lox::new();
+    let mut input = String::new();
+
+    loop {
+        if let Some(helper) = editor.helper_mut() {
+            helper.globals = lox.globals().into_iter().map(|(name, _)| name).collect();
+        }
+
+        let prompt = if input.is_empty() {
+            PROMPT
+        } else {
+            CONTINUATION_PROMPT
+        };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            // Ctrl-C drops what was typed so far
+            Err(ReadlineError::Interrupted) => {
+                input.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                break;
+            }
+        };
+        let _ = editor.add_history_entry(line.as_str());
+
+        if input.is_empty() && line.trim_start().starts_with(':') {
+            match parse_command(line.trim()) {
+                Ok(Command::Help) => println!("{}", HELP),
+                Ok(Command::Env) => print_globals(&lox),
+                Ok(Command::Load(filename)) => load(&mut lox, filename),
+                Ok(Command::Reset) => lox = Lox::new(),
+                Ok(Command::Quit) => break,
+                Err(message) => eprintln!("Error: {}", message),
+            }
+            continue;
+        }
+
+        input += &line;
+        input.push('\n');
+        if !is_complete(&input) {
+            continue;
+        }
+
+        if let Err(errors) = run_echo(&mut lox, &input, &mut std::io::stdout()) {
+            lox.report(&errors, "<repl>", &input);
+        }
+        input.clear();
+    }
+
+    if let Some(path) = &history_path {
+        if let Err(e) = editor.save_history(path) {
+            eprintln!(
+                "Error: Failed to save history to '{}': {}",
+                path.display(),
+                e
+            );
+        }
+    }
+}
+
+fn parse_command(line: &str) -> Result<Command<'_>, String> {
+    let (name, argument) = match line.split_once(char::is_whitespace) {
+        Some((name, argument)) => (name, argument.trim()),
+        None => (line, ""),
+    };
+
+    match (name, argument) {
+        (":help", "") => Ok(Command::Help),
+        (":env", "") => Ok(Command::Env),
+        (":load", "") => Err("':load' expects a file name".to_string()),
+        (":load", filename) => Ok(Command::Load(filename)),
+        (":reset", "") => Ok(Command::Reset),
+        (":quit", "") => Ok(Command::Quit),
+        (":help" | ":env" | ":reset" | ":quit", _) => {
+            Err(format!("'{}' doesn't take an argument", name))
+        }
+        _ => Err(format!("Unknown command '{}', try ':help'", name)),
+    }
+}
+
+fn print_globals(lox: &Lox) {
+    for (name, value) in lox.globals() {
+        println!("{} = {}", name, value);
+    }
+}
+
+fn load(lox: &mut Lox, filename: &str) {
+    let source = match std::fs::read_to_string(filename) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("Error: Failed to read file '{}': {}", filename, e);
+            return;
+        }
+    };
+
+    if let Err(errors) = lox.run(&source) {
+        lox.report(&errors, filename, &source);
+    }
+}
+
+// Runs the input and prints the value when it ends in an expression
+fn run_echo(lox: &mut Lox, input: &str, echo: &mut impl Write) -> Result<(), Vec<LoxError>> {
+    // allow leaving off the ';' after a lone expression
+    let with_semicolon = format!("{};", input);
+    let source = if Lox::check(input).is_err() && Lox::check(&with_semicolon).is_ok() {
+        &with_semicolon
+    } else {
+        input
+    };
+
+    if let Some(value) = lox.run(source)? {
+        // a failed echo isn't worth stopping the session for
+        let _ = writeln!(echo, "{}", value);
+    }
+
+    Ok(())
+}
+
+// Whether the input closes every bracket it opens, so it can be run. Text in
+// strings and comments is skipped, an unterminated string is incomplete too
+fn is_complete(input: &str) -> bool {
+    let mut depth = 0;
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' | '{' | '[' => depth += 1,
+            ')' | '}' | ']' => depth -= 1,
+            '"' => loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some(_) => {}
+                    None => return false,
+                }
+            },
+            '/' if chars.peek() == Some(&'/') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // a stray closing bracket can't be fixed by reading more, let the parser report it
+    depth <= 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn complete_input() {
+        assert!(is_complete("print 1;\n"));
+        assert!(is_complete("fun f() { return 1; }\n"));
+        assert!(!is_complete("fun f() {\n"));
+        assert!(!is_complete("print [1,\n"));
+        assert!(!is_complete("print (1 +\n"));
+        assert!(is_complete("print \"{\";\n"));
+        assert!(!is_complete("print \"a\n"));
+        assert!(is_complete("print 1; // {\n"));
+        assert!(is_complete("}\n"));
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(parse_command(":help"), Ok(Command::Help));
+        assert_eq!(parse_command(":env"), Ok(Command::Env));
+        assert_eq!(
+            parse_command(":load  scripts/a.lox"),
+            Ok(Command::Load("scripts/a.lox"))
+        );
+        assert_eq!(parse_command(":reset"), Ok(Command::Reset));
+        assert_eq!(parse_command(":quit"), Ok(Command::Quit));
+        assert!(parse_command(":load").is_err());
+        assert!(parse_command(":quit now").is_err());
+        assert!(parse_command(":exit").is_err());
+    }
+
+    #[test]
+    fn completion() {
+        let helper = LoxHelper {
+            globals: vec!["counter".to_string(), "clock".to_string()],
+        };
+        let complete = |line: &str| {
+            let start = word_start(line, line.len());
+            helper.candidates(line, start, &line[start..])
+        };
+
+        assert_eq!(complete("print co"), vec!["continue", "counter"]);
+        assert_eq!(complete("c"), vec!["class", "clock", "continue", "counter"]);
+        assert_eq!(complete("x = f"), vec!["false", "for", "fun"]);
+        assert_eq!(complete(":r"), vec![":reset"]);
+        assert_eq!(complete(":load cl"), Vec::<String>::new());
+        assert_eq!(complete("print "), Vec::<String>::new());
+    }
+
+    #[test]
+    fn echo_keeps_state() {
+        let mut lox = Lox::new();
+        let mut echo = vec![];
+        assert!(run_echo(&mut lox, "var a = 1;\n", &mut echo).is_ok());
+        assert!(run_echo(&mut lox, "a + 2\n", &mut echo).is_ok());
+        assert!(run_echo(&mut lox, "fun f() {\n  return a;\n}\nf();\n", &mut echo).is_ok());
+        assert!(run_echo(&mut lox, "a +\n", &mut echo).is_err());
+        assert_eq!(String::from_utf8(echo).unwrap(), "3\n1\n");
+    }
+
+    #[test]
+    fn echo_nil() {
+        let mut lox = Lox::new();
+        let mut echo = vec![];
+        assert!(run_echo(&mut lox, "nil\n", &mut echo).is_ok());
+        assert!(run_echo(&mut lox, "fun f() {}\n", &mut echo).is_ok());
+        assert!(run_echo(&mut lox, "f()\n", &mut echo).is_ok());
+        assert_eq!(String::from_utf8(echo).unwrap(), "nil\nnil\n");
+    }
+}
@@ -0,0 +1,638 @@
+use crate::error::{ErrorDetail, ErrorKind, Hint, LoxError};
+use crate::expr;
+use crate::stmt;
+use crate::symbol::{self, Symbol};
+use crate::token::Span;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionType {
+    None,
+    Function,
+    Method,
+    Initializer,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ClassType {
+    None,
+    Class,
+    Subclass,
+}
+
+#[derive(Default)]
+struct Scope {
+    // a name maps to whether its initializer has finished
+    names: HashMap<Symbol, bool>,
+    // every name in the order it was declared, and whether it names a function
+    declared: Vec<(Symbol, bool)>,
+    // functions declared in this scope, with where they were declared. Their
+    // bodies are resolved when the scope ends, so they can call functions
+    // declared after them
+    functions: Vec<(Rc<stmt::Function>, usize, Span)>,
+}
+
+struct Resolver {
+    // one per local scope
+    scopes: Vec<Scope>,
+    current_function: FunctionType,
+    current_class: ClassType,
+    // the innermost node being resolved, errors point at it
+    current_span: Span,
+    errors: Vec<LoxError>,
+}
+
+pub fn resolve(stmts: &[stmt::Stmt]) -> Result<(), Vec<LoxError>> {
+    let mut resolver = Resolver {
+        scopes: vec![],
+        current_function: FunctionType::None,
+        current_class: ClassType::None,
+        current_span: Span::default(),
+        errors: vec![],
+    };
+
+    resolver.resolve_stmts(stmts);
+
+    if resolver.errors.is_empty() {
+        return Ok(());
+    }
+
+    // local function bodies are resolved after the rest of their scope
+    resolver.errors.sort_by_key(|error| error.detail().line);
+    return Err(resolver.errors);
+}
+
+impl Resolver {
+    fn resolve_stmts(&mut self, stmts: &[stmt::Stmt]) {
+        for stmt in stmts {
+            self.resolve_stmt(stmt);
+        }
+    }
+
+    fn resolve_stmt(&mut self, stmt: &stmt::Stmt) {
+        let enclosing_span = std::mem::replace(&mut self.current_span, stmt.span);
+        stmt.accept(self);
+        self.current_span = enclosing_span;
+    }
+
+    fn resolve_expr(&mut self, expr: &expr::Expr) {
+        let enclosing_span = std::mem::replace(&mut self.current_span, expr.span);
+        expr.accept(self);
+        self.current_span = enclosing_span;
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(Scope::default());
+    }
+
+    fn end_scope(&mut self) {
+        let functions = match self.scopes.last_mut() {
+            Some(scope) => std::mem::take(&mut scope.functions),
+            None => return,
+        };
+
+        // a function body sees every function in its scope, but only the
+        // variables declared before the function
+        for (function, declared_before, span) in functions {
+            let scope = self.scopes.last_mut().unwrap();
+            let hidden: Vec<Symbol> = scope.declared[declared_before..]
+                .iter()
+                .filter(|(_, is_function)| !is_function)
+                .map(|(name, _)| *name)
+                .collect();
+            for name in &hidden {
+                scope.names.remove(name);
+            }
+
+            let enclosing_span = std::mem::replace(&mut self.current_span, span);
+            self.resolve_function(&function, FunctionType::Function);
+            self.current_span = enclosing_span;
+
+            let scope = self.scopes.last_mut().unwrap();
+            for name in hidden {
+                scope.names.insert(name, true);
+            }
+        }
+
+        self.scopes.pop();
+    }
+
+    fn error(&mut self, kind: ErrorKind, line: u32, name: &str, message: &str) {
+        self.errors.push(LoxError::Resolve(Box::new(ErrorDetail {
+            span: Some(self.current_span),
+            ..ErrorDetail::at_lexeme(kind, message.to_string(), name, line)
+        })));
+    }
+
+    // attaches a help line to the error reported last
+    fn help(&mut self, help: &str) {
+        if let Some(error) = self.errors.last_mut() {
+            error.detail_mut().hints.push(Hint::Help(help.to_string()));
+        }
+    }
+
+    fn declare(&mut self, name: Symbol, line: u32) {
+        self.declare_as(name, line, false);
+    }
+
+    fn declare_as(&mut self, name: Symbol, line: u32, is_function: bool) {
+        let already_declared = match self.scopes.last_mut() {
+            None => return,
+            Some(scope) => {
+                scope.declared.push((name, is_function));
+                scope.names.insert(name, false).is_some()
+            }
+        };
+
+        if already_declared {
+            self.error(
+                ErrorKind::Redeclaration,
+                line,
+                &name.name(),
+                "Already a variable with this name in this scope",
+            );
+        }
+    }
+
+    fn define(&mut self, name: Symbol) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.names.insert(name, true);
+        }
+    }
+
+    fn resolve_local(&self, name: Symbol) -> Option<usize> {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if scope.names.contains_key(&name) {
+                return Some(depth);
+            }
+        }
+
+        None
+    }
+
+    fn resolve_function(&mut self, function: &stmt::Function, function_type: FunctionType) {
+        let enclosing_function = self.current_function;
+        self.current_function = function_type;
+
+        self.begin_scope();
+        for parameter in &function.parameters {
+            self.declare(*parameter, function.line);
+            self.define(*parameter);
+        }
+        self.resolve_stmts(&function.statements);
+        self.end_scope();
+
+        self.current_function = enclosing_function;
+    }
+}
+
+impl stmt::StmtVisitor<()> for Resolver {
+    fn visit_expr(&mut self, expr: &expr::Expr) {
+        self.resolve_expr(expr);
+    }
+
+    fn visit_print(&mut self, print: &stmt::Print) {
+        for expr in &print.exprs {
+            self.resolve_expr(expr);
+        }
+    }
+
+    fn visit_if(&mut self, if_ctx: &stmt::If) {
+        self.resolve_expr(&if_ctx.condition);
+        self.resolve_stmt(&if_ctx.true_branch);
+        if let Some(branch) = &if_ctx.else_branch {
+            self.resolve_stmt(branch);
+        }
+    }
+
+    fn visit_block(&mut self, block: &stmt::Block) {
+        self.begin_scope();
+        self.resolve_stmts(&block.statements);
+        self.end_scope();
+    }
+
+    fn visit_var(&mut self, var: &stmt::Var) {
+        self.declare(var.name, var.line);
+        if let Some(initializer) = &var.initializer {
+            self.resolve_expr(initializer);
+        }
+        self.define(var.name);
+    }
+
+    fn visit_while(&mut self, while_ctx: &stmt::While) {
+        self.resolve_expr(&while_ctx.condition);
+        self.resolve_stmt(&while_ctx.body);
+        if let Some(increment) = &while_ctx.increment {
+            self.resolve_expr(increment);
+        }
+    }
+
+    fn visit_function(&mut self, function: &Rc<stmt::Function>) {
+        self.declare_as(function.name, function.line, true);
+        self.define(function.name);
+
+        match self.scopes.last_mut() {
+            Some(scope) => {
+                let declared_before = scope.declared.len();
+                scope
+                    .functions
+                    .push((function.clone(), declared_before, self.current_span));
+            }
+            None => self.resolve_function(function, FunctionType::Function),
+        }
+    }
+
+    fn visit_return(&mut self, return_ctx: &stmt::Return) {
+        if self.current_function == FunctionType::None {
+            self.error(
+                ErrorKind::InvalidReturn,
+                return_ctx.line,
+                "return",
+                "Can't return from top-level code",
+            );
+        }
+
+        if let Some(value) = &return_ctx.value {
+            if self.current_function == FunctionType::Initializer {
+                self.error(
+                    ErrorKind::InvalidReturn,
+                    return_ctx.line,
+                    "return",
+                    "Can't return a value from an initializer",
+                );
+                self.help("an initializer always returns 'this', use 'return;' to leave early");
+            }
+
+            self.resolve_expr(value);
+        }
+    }
+
+    fn visit_class(&mut self, class: &stmt::Class) {
+        let enclosing_class = self.current_class;
+        self.current_class = ClassType::Class;
+
+        self.declare(class.name, class.line);
+        self.define(class.name);
+
+        if let Some(superclass) = &class.superclass {
+            self.current_class = ClassType::Subclass;
+            expr::ExprVisitor::visit_variable(self, superclass);
+
+            self.begin_scope();
+            self.define(symbol::SUPER);
+        }
+
+        self.begin_scope();
+        self.define(symbol::THIS);
+
+        for method in &class.methods {
+            let function_type = if method.name == symbol::INIT {
+                FunctionType::Initializer
+            } else {
+                FunctionType::Method
+            };
+
+            self.resolve_function(method, function_type);
+        }
+
+        self.end_scope();
+
+        if class.superclass.is_some() {
+            self.end_scope();
+        }
+
+        self.current_class = enclosing_class;
+    }
+
+    fn visit_break(&mut self) {}
+
+    fn visit_continue(&mut self) {}
+}
+
+impl expr::ExprVisitor<()> for Resolver {
+    fn visit_literal_bool(&self, _literal_bool: &bool) {}
+
+    fn visit_literal_str(&self, _literal_str: &str) {}
+
+    fn visit_literal_number(&self, _literal_number: &f64) {}
+
+    fn visit_literal_int(&self, _literal_int: &i64) {}
+
+    fn visit_binary(&mut self, binary: &expr::Binary) {
+        self.resolve_expr(&binary.left);
+        self.resolve_expr(&binary.right);
+    }
+
+    fn visit_logical(&mut self, logical: &expr::Logical) {
+        self.resolve_expr(&logical.left);
+        self.resolve_expr(&logical.right);
+    }
+
+    fn visit_grouping(&mut self, grouping: &expr::Expr) {
+        self.resolve_expr(grouping);
+    }
+
+    fn visit_logical_not(&mut self, expr: &expr::Expr) {
+        self.resolve_expr(expr);
+    }
+
+    fn visit_unary_negate(&mut self, negate: &expr::UnaryNegate) {
+        self.resolve_expr(&negate.expr);
+    }
+
+    fn visit_variable(&mut self, variable: &expr::Variable) {
+        let in_own_initializer = match self.scopes.last() {
+            Some(scope) => scope.names.get(&variable.name) == Some(&false),
+            None => false,
+        };
+
+        if in_own_initializer {
+            self.error(
+                ErrorKind::ReadInOwnInitializer,
+                variable.line,
+                &variable.name.name(),
+                "Can't read local variable in its own initializer",
+            );
+        }
+
+        variable.depth.set(self.resolve_local(variable.name));
+    }
+
+    fn visit_assignment(&mut self, assignment: &expr::Assignment) {
+        self.resolve_expr(&assignment.expr);
+        assignment.depth.set(self.resolve_local(assignment.target));
+    }
+
+    fn visit_call(&mut self, call: &expr::Call) {
+        self.resolve_expr(&call.callee);
+        for argument in &call.arguments {
+            self.resolve_expr(argument);
+        }
+    }
+
+    fn visit_get(&mut self, get: &expr::Get) {
+        self.resolve_expr(&get.object);
+    }
+
+    fn visit_set(&mut self, set: &expr::Set) {
+        self.resolve_expr(&set.value);
+        self.resolve_expr(&set.object);
+    }
+
+    fn visit_this(&mut self, this: &expr::This) {
+        if self.current_class == ClassType::None {
+            self.error(
+                ErrorKind::InvalidThis,
+                this.line,
+                "this",
+                "Can't use 'this' outside of a class",
+            );
+        }
+
+        this.depth.set(self.resolve_local(symbol::THIS));
+    }
+
+    fn visit_super(&mut self, sup: &expr::Super) {
+        match self.current_class {
+            ClassType::None => self.error(
+                ErrorKind::InvalidSuper,
+                sup.line,
+                "super",
+                "Can't use 'super' outside of a class",
+            ),
+            ClassType::Class => self.error(
+                ErrorKind::InvalidSuper,
+                sup.line,
+                "super",
+                "Can't use 'super' in a class with no superclass",
+            ),
+            ClassType::Subclass => {}
+        }
+
+        sup.depth.set(self.resolve_local(symbol::SUPER));
+    }
+
+    fn visit_lambda(&mut self, function: &Rc<stmt::Function>) {
+        self.resolve_function(function, FunctionType::Function);
+    }
+
+    fn visit_list(&mut self, elements: &[expr::Expr]) {
+        for element in elements {
+            self.resolve_expr(element);
+        }
+    }
+
+    fn visit_map(&mut self, map: &expr::Map) {
+        for (key, value) in &map.entries {
+            self.resolve_expr(key);
+            self.resolve_expr(value);
+        }
+    }
+
+    fn visit_index(&mut self, index: &expr::Index) {
+        self.resolve_expr(&index.object);
+        self.resolve_expr(&index.index);
+    }
+
+    fn visit_index_set(&mut self, index_set: &expr::IndexSet) {
+        self.resolve_expr(&index_set.object);
+        self.resolve_expr(&index_set.index);
+        self.resolve_expr(&index_set.value);
+    }
+
+    fn visit_nil(&self) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser;
+    use crate::scanner;
+
+    fn resolve_source(source: &str) -> Result<Vec<stmt::Stmt>, Vec<LoxError>> {
+        let tokens = scanner::scan(source)?;
+        let stmts = parser::parse(&tokens)?;
+        resolve(&stmts)?;
+        Ok(stmts)
+    }
+
+    #[test]
+    fn local_depths() {
+        let stmts = resolve_source("var g; { var a; { a = g; } }").unwrap();
+
+        let inner_block = match &stmts[1].kind {
+            stmt::StmtKind::Block(outer) => match &outer.statements[1].kind {
+                stmt::StmtKind::Block(inner) => inner,
+                s => panic!("Expected inner block, found {:?}", s),
+            },
+            s => panic!("Expected outer block, found {:?}", s),
+        };
+
+        match &inner_block.statements[0].kind {
+            stmt::StmtKind::Expr(expr::Expr {
+                kind: expr::ExprKind::Assignment(assignment),
+                ..
+            }) => {
+                assert_eq!(assignment.depth.get(), Some(1));
+                match &assignment.expr.kind {
+                    expr::ExprKind::Variable(variable) => assert_eq!(variable.depth.get(), None),
+                    e => panic!("Expected variable, found {:?}", e),
+                }
+            }
+            s => panic!("Expected assignment, found {:?}", s),
+        }
+    }
+
+    #[test]
+    fn global_self_initializer() {
+        assert!(resolve_source("var a = 1; var a = a;").is_ok());
+    }
+
+    #[test]
+    fn local_self_initializer() {
+        assert_eq!(resolve_source("{ var a = a; }").unwrap_err().len(), 1);
+    }
+
+    #[test]
+    fn local_redeclaration() {
+        let errors = resolve_source("{ var a; var a; }").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(
+            &errors[0],
+            LoxError::Resolve(detail) if detail.kind == ErrorKind::Redeclaration
+        ));
+        assert_eq!(
+            errors[0].to_string(),
+            "Line 1 at 'a': Already a variable with this name in this scope"
+        );
+        assert_eq!(resolve_source("fun f(a) { var a; }").unwrap_err().len(), 1);
+    }
+
+    #[test]
+    fn top_level_return() {
+        assert_eq!(resolve_source("return 1;").unwrap_err().len(), 1);
+        assert!(resolve_source("fun f() { return 1; }").is_ok());
+    }
+
+    #[test]
+    fn this_and_super() {
+        assert_eq!(resolve_source("print this;").unwrap_err().len(), 1);
+        assert_eq!(
+            resolve_source("class A { f() { return super.f(); } }")
+                .unwrap_err()
+                .len(),
+            1
+        );
+        assert!(resolve_source(
+            "class A { f() {} } class B < A { f() { return fun () { return super.f; }; } }"
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn initializer_return() {
+        assert_eq!(
+            resolve_source("class A { init() { return 1; } }")
+                .unwrap_err()
+                .len(),
+            1
+        );
+        assert!(resolve_source("class A { init() { return; } }").is_ok());
+    }
+
+    #[test]
+    fn local_functions_see_later_functions() {
+        let stmts = resolve_source("{ fun even() { return odd(); } fun odd() { return even(); } }")
+            .unwrap();
+
+        let even = match &stmts[0].kind {
+            stmt::StmtKind::Block(block) => match &block.statements[0].kind {
+                stmt::StmtKind::Function(function) => function.clone(),
+                s => panic!("Expected function, found {:?}", s),
+            },
+            s => panic!("Expected block, found {:?}", s),
+        };
+
+        match &even.statements[0].kind {
+            stmt::StmtKind::Return(stmt::Return {
+                value:
+                    Some(expr::Expr {
+                        kind: expr::ExprKind::Call(call),
+                        ..
+                    }),
+                ..
+            }) => match &call.callee.kind {
+                expr::ExprKind::Variable(variable) => assert_eq!(variable.depth.get(), Some(1)),
+                e => panic!("Expected variable, found {:?}", e),
+            },
+            s => panic!("Expected return, found {:?}", s),
+        }
+
+        assert_eq!(
+            resolve_source("{ fun f() {} fun f() {} }")
+                .unwrap_err()
+                .len(),
+            1
+        );
+        assert_eq!(
+            resolve_source("{ var f; fun f() {} }").unwrap_err().len(),
+            1
+        );
+
+        // code before the declaration still sees the global
+        let stmts = resolve_source("fun f() {} { f(); fun f() {} }").unwrap();
+        match &stmts[1].kind {
+            stmt::StmtKind::Block(block) => match &block.statements[0].kind {
+                stmt::StmtKind::Expr(expr::Expr {
+                    kind: expr::ExprKind::Call(call),
+                    ..
+                }) => match &call.callee.kind {
+                    expr::ExprKind::Variable(variable) => assert_eq!(variable.depth.get(), None),
+                    e => panic!("Expected variable, found {:?}", e),
+                },
+                s => panic!("Expected call, found {:?}", s),
+            },
+            s => panic!("Expected block, found {:?}", s),
+        }
+    }
+
+    #[test]
+    fn errors_in_source_order() {
+        let errors =
+            resolve_source("{\n  fun f() {\n    return this;\n  }\n  var a = a;\n}").unwrap_err();
+        let lines: Vec<Option<u32>> = errors.iter().map(|e| e.detail().line).collect();
+        assert_eq!(lines, vec![Some(3), Some(5)]);
+    }
+
+    #[test]
+    fn variables_not_hoisted() {
+        let stmts = resolve_source("{ fun show() { print a; } var a; }").unwrap();
+
+        match &stmts[0].kind {
+            stmt::StmtKind::Block(block) => match &block.statements[0].kind {
+                stmt::StmtKind::Function(function) => match &function.statements[0].kind {
+                    stmt::StmtKind::Print(print) => match &print.exprs[0].kind {
+                        expr::ExprKind::Variable(variable) => {
+                            assert_eq!(variable.depth.get(), None)
+                        }
+                        e => panic!("Expected variable, found {:?}", e),
+                    },
+                    s => panic!("Expected print, found {:?}", s),
+                },
+                s => panic!("Expected function, found {:?}", s),
+            },
+            s => panic!("Expected block, found {:?}", s),
+        }
+    }
+
+    #[test]
+    fn reports_every_error() {
+        assert_eq!(
+            resolve_source("return; { var a; var a; } print this;")
+                .unwrap_err()
+                .len(),
+            3
+        );
+    }
+}
@@ -1,9 +1,15 @@
-use crate::token::{Token, TokenType};
+use crate::error::{ErrorDetail, ErrorKind, LoxError};
+use crate::token::{Span, Token, TokenType};
 use phf::phf_map;
 use std::str::CharIndices;
 use std::vec::Vec;
 
-pub fn scan(source: &str) -> Result<Vec<Token>, Vec<String>> {
+// the reserved words of the language, for completion in the REPL
+pub fn keywords() -> impl Iterator<Item = &'static str> {
+    Scanner::KEYWORDS.keys().copied()
+}
+
+pub fn scan(source: &str) -> Result<Vec<Token>, Vec<LoxError>> {
     let mut chars = source.char_indices();
     let eof = (source.len(), '\0');
     let current = match chars.next() {
@@ -17,6 +23,8 @@ pub fn scan(source: &str) -> Result<Vec<Token>, Vec<String>> {
         current,
         eof,
         line: 1,
+        line_start: 0,
+        token_start: 0,
         tokens: vec![],
         errors: vec![],
     };
@@ -30,14 +38,20 @@ struct Scanner<'a> {
     current: (usize, char),
     eof: (usize, char),
     line: u32,
+    // byte offset of the first character on the current line
+    line_start: usize,
+    // byte offset of the first character of the token being scanned
+    token_start: usize,
     tokens: Vec<Token>,
-    errors: Vec<String>,
+    errors: Vec<LoxError>,
 }
 
 impl<'a> Scanner<'a> {
     const KEYWORDS: phf::Map<&'static str, TokenType> = phf_map! {
         "and" => TokenType::And,
+        "break" => TokenType::Break,
         "class" => TokenType::Class,
+        "continue" => TokenType::Continue,
         "else" => TokenType::Else,
         "false" => TokenType::False,
         "fun" => TokenType::Fun,
@@ -70,12 +84,18 @@ impl<'a> Scanner<'a> {
         self.current.0
     }
 
-    fn scan_tokens(mut self) -> Result<Vec<Token>, Vec<String>> {
+    fn scan_tokens(mut self) -> Result<Vec<Token>, Vec<LoxError>> {
         while self.current != self.eof {
             self.scan_token();
         }
 
-        self.tokens.push(Token::new(TokenType::Eof, self.line));
+        let end = self.source.len();
+        self.tokens.push(Token::new_at(
+            TokenType::Eof,
+            self.line,
+            self.column_at(end),
+            Span { start: end, end },
+        ));
 
         if self.errors.is_empty() {
             return Ok(self.tokens);
@@ -86,9 +106,11 @@ impl<'a> Scanner<'a> {
 
     fn scan_token(&mut self) {
         let ch = self.advance();
+        self.token_start = ch.0;
         match ch.1 {
             '\n' => {
                 self.line += 1;
+                self.line_start = ch.0 + 1;
                 return;
             }
             ch if ch.is_whitespace() => return,
@@ -104,12 +126,21 @@ impl<'a> Scanner<'a> {
             ')' => self.add_token(TokenType::RightParen),
             '{' => self.add_token(TokenType::LeftBrace),
             '}' => self.add_token(TokenType::RightBrace),
+            '[' => self.add_token(TokenType::LeftBracket),
+            ']' => self.add_token(TokenType::RightBracket),
             ',' => self.add_token(TokenType::Comma),
+            ':' => self.add_token(TokenType::Colon),
             '.' => self.add_token(TokenType::Dot),
             '-' => self.add_token(TokenType::Minus),
             '+' => self.add_token(TokenType::Plus),
             ';' => self.add_token(TokenType::SemiColon),
             '*' => self.add_token(TokenType::Star),
+            '%' => self.add_token(TokenType::Percent),
+            // integer division, '//' already starts a comment
+            '~' if self.current.1 == '/' => {
+                self.advance();
+                self.add_token(TokenType::TildeSlash);
+            }
             '!' => {
                 let token = self.match_char('=', TokenType::BangEqual, TokenType::Bang);
                 self.add_token(token)
@@ -132,50 +163,193 @@ impl<'a> Scanner<'a> {
                 } else if ch.1.is_ascii_alphabetic() || ch.1 == '_' {
                     self.identifier(ch.0)
                 } else {
-                    self.errors
-                        .push(format!("Invalid character {} at line {}", ch.1, self.line));
+                    self.error(
+                        ErrorKind::InvalidCharacter,
+                        format!("Invalid character {}", ch.1),
+                        ch.0,
+                        ch.0 + ch.1.len_utf8(),
+                    );
                 }
             }
         }
     }
 
+    fn column_at(&self, offset: usize) -> u32 {
+        self.source[self.line_start..offset].chars().count() as u32 + 1
+    }
+
+    fn error(&mut self, kind: ErrorKind, message: String, start: usize, end: usize) {
+        self.errors.push(LoxError::Scan(Box::new(ErrorDetail {
+            line: Some(self.line),
+            column: Some(self.column_at(start)),
+            span: Some(Span { start, end }),
+            ..ErrorDetail::new(kind, message)
+        })));
+    }
+
     fn add_token(&mut self, token_type: TokenType) {
-        self.tokens.push(Token::new(token_type, self.line));
+        let span = Span {
+            start: self.token_start,
+            end: self.current.0,
+        };
+        let column = self.column_at(span.start);
+        self.tokens
+            .push(Token::new_at(token_type, self.line, column, span));
     }
 
     fn number(&mut self, start: usize) {
-        let mut end = self.advance_while(|c| c.is_ascii_digit());
+        match self.number_literal(start) {
+            Ok(token_type) => self.add_token(token_type),
+            Err((message, error_start, error_end)) => {
+                self.error(ErrorKind::InvalidNumber, message, error_start, error_end);
+                // skip what's left of the literal rather than scanning it as more tokens
+                self.advance_while(|c| c.is_alphanumeric() || c == '_');
+            }
+        }
+    }
+
+    // Scans a literal whose first digit, at 'start', was already consumed.
+    // Literals with a fraction or an exponent are floats, the rest are ints.
+    // Errors are a message and the span it points at
+    fn number_literal(&mut self, start: usize) -> Result<TokenType, (String, usize, usize)> {
+        let prefix = match (&self.source[start..start + 1], self.current.1) {
+            ("0", 'x') => Some((16, "hexadecimal")),
+            ("0", 'o') => Some((8, "octal")),
+            ("0", 'b') => Some((2, "binary")),
+            _ => None,
+        };
+
+        if let Some((radix, name)) = prefix {
+            self.advance();
+            let digits_start = self.current.0;
+            let count = self.digits(radix)?;
+
+            let (offset, c) = self.current;
+            if c.is_ascii_alphanumeric() {
+                return Err((
+                    format!("Invalid digit '{}' in {} literal", c, name),
+                    offset,
+                    offset + c.len_utf8(),
+                ));
+            }
+            if count == 0 {
+                return Err((
+                    format!(
+                        "Expected {} digits after '{}'",
+                        name,
+                        &self.source[start..digits_start]
+                    ),
+                    start,
+                    digits_start,
+                ));
+            }
+
+            let literal = &self.source[start..offset];
+            let digits = self.source[digits_start..offset].replace('_', "");
+            return i64::from_str_radix(&digits, radix)
+                .map(TokenType::Int)
+                .map_err(|_| {
+                    (
+                        format!("Number literal '{}' is too large", literal),
+                        start,
+                        offset,
+                    )
+                });
+        }
+
+        self.digits(10)?;
 
         let is_next_digit = || match self.chars.clone().next() {
             None => false,
             Some(x) => x.1.is_ascii_digit(),
         };
 
+        let mut is_float = false;
         if self.current.1 == '.' && is_next_digit() {
             self.advance();
-            end = self.advance_while(|c| c.is_ascii_digit());
+            self.digits(10)?;
+            is_float = true;
         }
 
-        if self.current.1.is_ascii_alphabetic() {
-            self.errors.push(format!(
-                "Unexpected character '{}' after number at line {}",
-                self.current.1, self.line
+        if matches!(self.current.1, 'e' | 'E') {
+            let exponent_start = self.current.0;
+            self.advance();
+            if matches!(self.current.1, '+' | '-') {
+                self.advance();
+            }
+            if !self.current.1.is_ascii_digit() {
+                return Err((
+                    "Expected digits in the exponent of number literal".to_string(),
+                    exponent_start,
+                    self.current.0,
+                ));
+            }
+            self.digits(10)?;
+            is_float = true;
+        }
+
+        let (end, c) = self.current;
+        if c.is_ascii_alphabetic() {
+            return Err((
+                format!("Unexpected character '{}' after number", c),
+                end,
+                end + c.len_utf8(),
             ));
         }
 
-        let s = &self.source[start..end];
-        let value: f32 = s.parse().expect(&format!(
-            "Expected token string to be a valid number. String: {}",
-            s
-        ));
-        self.add_token(TokenType::Number(value));
+        let literal = &self.source[start..end];
+        let s = literal.replace('_', "");
+        let token_type = if is_float {
+            // parsing saturates to infinity rather than failing
+            s.parse::<f64>()
+                .ok()
+                .filter(|value| value.is_finite())
+                .map(TokenType::Number)
+        } else {
+            s.parse::<i64>().ok().map(TokenType::Int)
+        };
+
+        token_type.ok_or_else(|| {
+            (
+                format!("Number literal '{}' is too large", literal),
+                start,
+                end,
+            )
+        })
+    }
+
+    // Consumes digits in the radix, and underscores that separate them.
+    // Returns how many digits there were
+    fn digits(&mut self, radix: u32) -> Result<usize, (String, usize, usize)> {
+        let mut count = 0;
+        loop {
+            let (offset, c) = self.current;
+            if c.is_digit(radix) {
+                count += 1;
+            } else if c == '_' {
+                let is_next_digit = match self.chars.clone().next() {
+                    None => false,
+                    Some(x) => x.1.is_digit(radix),
+                };
+                if !is_next_digit {
+                    return Err((
+                        "Expected a digit after '_' in number literal".to_string(),
+                        offset,
+                        offset + 1,
+                    ));
+                }
+            } else {
+                return Ok(count);
+            }
+            self.advance();
+        }
     }
 
     fn identifier(&mut self, start: usize) {
         let end = self.advance_while(|c| c.is_alphanumeric() || c == '_');
 
         let s = &self.source[start..end];
-        if let Some(token_type) = Scanner::KEYWORDS.get(&s) {
+        if let Some(token_type) = Scanner::KEYWORDS.get(s) {
             self.add_token(token_type.clone());
         } else {
             self.add_token(TokenType::Identifier(s.to_string()));
@@ -187,8 +361,16 @@ impl<'a> Scanner<'a> {
         let end = self.advance_while(|c| c != '"');
 
         let s = &self.source[start..end];
-        self.add_token(TokenType::Str(s.to_string()));
         self.advance();
+        self.add_token(TokenType::Str(s.to_string()));
+
+        // the token keeps the line it started on, later tokens count the newlines inside it
+        for (i, ch) in s.char_indices() {
+            if ch == '\n' {
+                self.line += 1;
+                self.line_start = start + i + 1;
+            }
+        }
     }
 
     fn match_char(
@@ -207,6 +389,7 @@ impl<'a> Scanner<'a> {
 }
 
 #[cfg(test)]
+#[allow(clippy::single_match, clippy::single_char_add_str)]
 mod test {
     use super::*;
 
@@ -291,7 +474,7 @@ mod test {
         };
 
         assert_eq!(tokens.len(), 2);
-        assert_eq!(tokens[0].token_type, TokenType::Number(1234.0));
+        assert_eq!(tokens[0].token_type, TokenType::Int(1234));
         assert_eq!(tokens[1].token_type, TokenType::Eof);
     }
 
@@ -303,7 +486,7 @@ mod test {
         };
 
         assert_eq!(tokens.len(), 4);
-        assert_eq!(tokens[0].token_type, TokenType::Number(1234.0));
+        assert_eq!(tokens[0].token_type, TokenType::Int(1234));
         assert_eq!(tokens[3].token_type, TokenType::Eof);
     }
 
@@ -339,6 +522,132 @@ mod test {
         };
     }
 
+    #[test]
+    fn large_integer() {
+        let tokens = scan("16777217.0 9007199254740993 9223372036854775807").unwrap();
+        assert_eq!(tokens[0].token_type, TokenType::Number(16777217.0));
+        assert_eq!(tokens[1].token_type, TokenType::Int(9007199254740993));
+        assert_eq!(tokens[2].token_type, TokenType::Int(i64::MAX));
+
+        let errors = scan("9223372036854775808").unwrap_err();
+        assert_eq!(errors[0].detail().kind, ErrorKind::InvalidNumber);
+    }
+
+    #[test]
+    fn extended_literals() {
+        let tokens =
+            scan("0xFF 0x_dead_BEEF 0o17 0b1010 1_000_000 6.02e23 1E-3 2.5e+2 1_0.0_1").unwrap();
+        let token_types: Vec<TokenType> = tokens.into_iter().map(|t| t.token_type).collect();
+        assert_eq!(
+            token_types,
+            vec![
+                TokenType::Int(255),
+                TokenType::Int(0xdead_beef),
+                TokenType::Int(15),
+                TokenType::Int(10),
+                TokenType::Int(1_000_000),
+                TokenType::Number(6.02e23),
+                TokenType::Number(0.001),
+                TokenType::Number(250.0),
+                TokenType::Number(10.01),
+                TokenType::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_literals() {
+        // the message and the source text it points at
+        fn error(source: &str) -> (String, &str) {
+            let errors = scan(source).unwrap_err();
+            assert_eq!(errors.len(), 1, "{:?}", errors);
+            let detail = errors[0].detail();
+            assert_eq!(detail.kind, ErrorKind::InvalidNumber);
+            let span = detail.span.unwrap();
+            (detail.message.clone(), &source[span.start..span.end])
+        }
+
+        assert_eq!(
+            error("0x;"),
+            ("Expected hexadecimal digits after '0x'".to_string(), "0x")
+        );
+        assert_eq!(
+            error("0b102"),
+            ("Invalid digit '2' in binary literal".to_string(), "2")
+        );
+        assert_eq!(
+            error("0o8"),
+            ("Invalid digit '8' in octal literal".to_string(), "8")
+        );
+        assert_eq!(
+            error("1e;"),
+            (
+                "Expected digits in the exponent of number literal".to_string(),
+                "e"
+            )
+        );
+        assert_eq!(
+            error("1.5e+"),
+            (
+                "Expected digits in the exponent of number literal".to_string(),
+                "e+"
+            )
+        );
+        assert_eq!(
+            error("1__000"),
+            (
+                "Expected a digit after '_' in number literal".to_string(),
+                "_"
+            )
+        );
+        assert_eq!(
+            error("100_"),
+            (
+                "Expected a digit after '_' in number literal".to_string(),
+                "_"
+            )
+        );
+        assert_eq!(
+            error("0x8000_0000_0000_0000"),
+            (
+                "Number literal '0x8000_0000_0000_0000' is too large".to_string(),
+                "0x8000_0000_0000_0000"
+            )
+        );
+        assert_eq!(
+            error("1e400"),
+            ("Number literal '1e400' is too large".to_string(), "1e400")
+        );
+    }
+
+    #[test]
+    fn integer_operators() {
+        let tokens = scan("7 % 2 ~/ 3 // comment").unwrap();
+        let token_types: Vec<TokenType> = tokens.into_iter().map(|t| t.token_type).collect();
+        assert_eq!(
+            token_types,
+            vec![
+                TokenType::Int(7),
+                TokenType::Percent,
+                TokenType::Int(2),
+                TokenType::TildeSlash,
+                TokenType::Int(3),
+                TokenType::Eof,
+            ]
+        );
+        assert!(scan("1 ~ 2").is_err());
+    }
+
+    #[test]
+    fn number_overflow() {
+        let source = format!("print 1{}.0;", "0".repeat(400));
+        let errors = scan(&source).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        let detail = errors[0].detail();
+        assert_eq!(detail.kind, ErrorKind::InvalidNumber);
+        assert_eq!(detail.span, Some(Span { start: 6, end: 409 }));
+    }
+
     #[test]
     fn non_alphanumeric() {
         let tokens = match scan("(){},.-+*/!!====<<=>>=") {
@@ -375,9 +684,33 @@ mod test {
         assert_eq!(tokens.len(), expected_tokens.len());
     }
 
+    #[test]
+    fn brackets_and_colon() {
+        let tokens = match scan("[1][]:") {
+            Ok(t) => t,
+            Err(e) => panic!("{:?}", e),
+        };
+
+        let expected_tokens = [
+            TokenType::LeftBracket,
+            TokenType::Int(1),
+            TokenType::RightBracket,
+            TokenType::LeftBracket,
+            TokenType::RightBracket,
+            TokenType::Colon,
+            TokenType::Eof,
+        ];
+
+        for (i, t) in expected_tokens.iter().enumerate() {
+            assert_eq!(tokens[i].token_type, *t);
+        }
+
+        assert_eq!(tokens.len(), expected_tokens.len());
+    }
+
     #[test]
     fn error() {
-        let errors = match scan("^&%") {
+        let errors = match scan("^&~") {
             Ok(_) => panic!("Expected scan error"),
             Err(e) => e,
         };
@@ -385,6 +718,51 @@ mod test {
         assert_eq!(errors.len(), 3);
     }
 
+    #[test]
+    fn positions() {
+        let tokens = match scan("var s = \"a\nb\";\n  s;") {
+            Ok(t) => t,
+            Err(e) => panic!("{:?}", e),
+        };
+
+        let positions: Vec<(u32, u32, usize, usize)> = tokens
+            .iter()
+            .map(|t| (t.line, t.column, t.span.start, t.span.end))
+            .collect();
+
+        assert_eq!(
+            positions,
+            vec![
+                (1, 1, 0, 3),
+                (1, 5, 4, 5),
+                (1, 7, 6, 7),
+                (1, 9, 8, 13),
+                (2, 3, 13, 14),
+                (3, 3, 17, 18),
+                (3, 4, 18, 19),
+                (3, 5, 19, 19),
+            ]
+        );
+    }
+
+    #[test]
+    fn error_location() {
+        let errors = match scan("var a;\n  a ^ 1;") {
+            Ok(_) => panic!("Expected scan error"),
+            Err(e) => e,
+        };
+
+        let detail = match &errors[0] {
+            LoxError::Scan(detail) => detail,
+            e => panic!("Expected scan error, found {:?}", e),
+        };
+        assert_eq!(detail.kind, ErrorKind::InvalidCharacter);
+        assert_eq!(detail.line, Some(2));
+        assert_eq!(detail.column, Some(5));
+        assert_eq!(detail.span, Some(Span { start: 11, end: 12 }));
+        assert_eq!(errors[0].to_string(), "Invalid character ^ at line 2");
+    }
+
     #[test]
     fn indentifier_with_underscore() {
         let tokens = match scan("indentifier_with_underscore") {
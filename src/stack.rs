@@ -0,0 +1,9 @@
+// Guards the recursion in the parser and interpreter, so runaway or deeply
+// nested scripts fail with an error rather than overflowing the native stack
+// of whatever thread they run on.
+
+// Whether fewer than 'reserve' bytes of stack are left. Always false where
+// the size of the stack can't be found.
+pub fn remaining_below(reserve: usize) -> bool {
+    stacker::remaining_stack().is_some_and(|remaining| remaining < reserve)
+}
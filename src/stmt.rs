@@ -1,4 +1,6 @@
 use crate::expr;
+use crate::symbol::Symbol;
+use crate::token::Span;
 use std::rc::Rc;
 use std::vec::Vec;
 
@@ -21,7 +23,7 @@ pub struct Print {
 
 #[derive(Debug, PartialEq)]
 pub struct Var {
-    pub name: String,
+    pub name: Symbol,
     pub line: u32,
     pub initializer: Option<expr::Expr>,
 }
@@ -30,20 +32,14 @@ pub struct Var {
 pub struct While {
     pub condition: expr::Expr,
     pub body: Box<Stmt>,
-}
-
-#[derive(Debug, PartialEq)]
-pub struct For {
-    pub initializer: Option<Box<Stmt>>,
-    pub condition: Option<expr::Expr>,
-    pub loop_eval: Option<expr::Expr>,
-    pub body: Box<Stmt>,
+    // evaluated after every iteration, including ones cut short by 'continue'
+    pub increment: Option<expr::Expr>,
 }
 
 #[derive(Debug, PartialEq)]
 pub struct Function {
-    pub name: String,
-    pub parameters: Vec<String>,
+    pub name: Symbol,
+    pub parameters: Vec<Symbol>,
     pub statements: Vec<Stmt>,
     pub line: u32,
 }
@@ -52,10 +48,28 @@ impl Function {
     pub fn arity(&self) -> u32 {
         self.parameters.len() as u32
     }
+
+    pub fn is_anonymous(&self) -> bool {
+        self.name.is_empty()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Return {
+    pub value: Option<expr::Expr>,
+    pub line: u32,
 }
 
 #[derive(Debug, PartialEq)]
-pub enum Stmt {
+pub struct Class {
+    pub name: Symbol,
+    pub line: u32,
+    pub superclass: Option<expr::Variable>,
+    pub methods: Vec<Rc<Function>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum StmtKind {
     Expr(expr::Expr),
     Print(Print),
     If(If),
@@ -63,7 +77,18 @@ pub enum Stmt {
     Var(Var),
     While(While),
     Function(Rc<Function>),
-    Return(expr::Expr),
+    Return(Return),
+    Class(Class),
+    Break,
+    Continue,
+}
+
+// 'span' covers the source text of the whole statement, it's left
+// empty for nodes that weren't parsed from source
+#[derive(Debug, PartialEq)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
 }
 
 pub trait StmtVisitor<T> {
@@ -74,73 +99,112 @@ pub trait StmtVisitor<T> {
     fn visit_var(&mut self, var: &Var) -> T;
     fn visit_while(&mut self, while_ctx: &While) -> T;
     fn visit_function(&mut self, function: &Rc<Function>) -> T;
-    fn visit_return(&mut self, expr: &expr::Expr) -> T;
+    fn visit_return(&mut self, return_ctx: &Return) -> T;
+    fn visit_class(&mut self, class: &Class) -> T;
+    fn visit_break(&mut self) -> T;
+    fn visit_continue(&mut self) -> T;
 }
 
 impl Stmt {
+    pub fn new(kind: StmtKind) -> Stmt {
+        Stmt {
+            kind,
+            span: Span::default(),
+        }
+    }
+
     pub fn accept<T>(&self, visitor: &mut impl StmtVisitor<T>) -> T {
-        match self {
-            Stmt::Expr(expr) => visitor.visit_expr(expr),
-            Stmt::Print(print) => visitor.visit_print(print),
-            Stmt::If(if_ctx) => visitor.visit_if(if_ctx),
-            Stmt::Block(block) => visitor.visit_block(block),
-            Stmt::Var(var) => visitor.visit_var(var),
-            Stmt::While(while_ctx) => visitor.visit_while(while_ctx),
-            Stmt::Function(function) => visitor.visit_function(function),
-            Stmt::Return(expr) => visitor.visit_return(expr),
+        match &self.kind {
+            StmtKind::Expr(expr) => visitor.visit_expr(expr),
+            StmtKind::Print(print) => visitor.visit_print(print),
+            StmtKind::If(if_ctx) => visitor.visit_if(if_ctx),
+            StmtKind::Block(block) => visitor.visit_block(block),
+            StmtKind::Var(var) => visitor.visit_var(var),
+            StmtKind::While(while_ctx) => visitor.visit_while(while_ctx),
+            StmtKind::Function(function) => visitor.visit_function(function),
+            StmtKind::Return(return_ctx) => visitor.visit_return(return_ctx),
+            StmtKind::Class(class) => visitor.visit_class(class),
+            StmtKind::Break => visitor.visit_break(),
+            StmtKind::Continue => visitor.visit_continue(),
         }
     }
 }
 
 pub fn new_expr(expr: expr::Expr) -> Stmt {
-    Stmt::Expr(expr)
+    Stmt::new(StmtKind::Expr(expr))
 }
 
 pub fn new_print(exprs: Vec<expr::Expr>) -> Stmt {
-    Stmt::Print(Print { exprs })
+    Stmt::new(StmtKind::Print(Print { exprs }))
 }
 
 pub fn new_if(condition: expr::Expr, true_branch: Stmt, else_branch: Option<Stmt>) -> Stmt {
-    Stmt::If(If {
+    Stmt::new(StmtKind::If(If {
         condition,
         true_branch: Box::new(true_branch),
-        else_branch: else_branch.map(|s| Box::new(s)),
-    })
+        else_branch: else_branch.map(Box::new),
+    }))
 }
 
 pub fn new_block(statements: Vec<Stmt>) -> Stmt {
-    Stmt::Block(Block { statements })
+    Stmt::new(StmtKind::Block(Block { statements }))
 }
 
 pub fn new_var(name: &str, line: u32, initializer: Option<expr::Expr>) -> Stmt {
-    Stmt::Var(Var {
-        name: name.to_string(),
+    Stmt::new(StmtKind::Var(Var {
+        name: Symbol::intern(name),
         line,
         initializer,
-    })
+    }))
 }
 
 pub fn new_while(condition: expr::Expr, body: Stmt) -> Stmt {
-    Stmt::While(While {
+    Stmt::new(StmtKind::While(While {
         condition,
         body: Box::new(body),
-    })
+        increment: None,
+    }))
 }
 
-pub fn new_function(
-    name: String,
-    parameters: Vec<String>,
-    statements: Vec<Stmt>,
-    line: u32,
-) -> Stmt {
-    Stmt::Function(Rc::new(Function {
-        name,
+pub fn new_while_with_increment(condition: expr::Expr, body: Stmt, increment: expr::Expr) -> Stmt {
+    Stmt::new(StmtKind::While(While {
+        condition,
+        body: Box::new(body),
+        increment: Some(increment),
+    }))
+}
+
+pub fn new_function(name: &str, parameters: Vec<Symbol>, statements: Vec<Stmt>, line: u32) -> Stmt {
+    Stmt::new(StmtKind::Function(Rc::new(Function {
+        name: Symbol::intern(name),
         parameters,
         statements,
         line,
+    })))
+}
+
+pub fn new_return(value: Option<expr::Expr>, line: u32) -> Stmt {
+    Stmt::new(StmtKind::Return(Return { value, line }))
+}
+
+pub fn new_class(
+    name: &str,
+    line: u32,
+    superclass: Option<expr::Variable>,
+    methods: Vec<Rc<Function>>,
+) -> Stmt {
+    Stmt::new(StmtKind::Class(Class {
+        name: Symbol::intern(name),
+        line,
+        superclass,
+        methods,
     }))
 }
 
-pub fn new_return(expr: expr::Expr) -> Stmt {
-    Stmt::Return(expr)
+pub fn new_break() -> Stmt {
+    Stmt::new(StmtKind::Break)
+}
+
+pub fn new_continue() -> Stmt {
+    Stmt::new(StmtKind::Continue)
 }
@@ -0,0 +1,101 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+// An interned identifier. Two symbols are equal exactly when their names are,
+// so environments can compare ids instead of hashing names on every access.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+// names interned up front so the interpreter can refer to them without a lookup
+pub const EMPTY: Symbol = Symbol(0);
+pub const THIS: Symbol = Symbol(1);
+pub const SUPER: Symbol = Symbol(2);
+pub const INIT: Symbol = Symbol(3);
+
+const PREDEFINED: [&str; 4] = ["", "this", "super", "init"];
+
+struct Interner {
+    ids: HashMap<Rc<str>, Symbol>,
+    names: Vec<Rc<str>>,
+}
+
+impl Interner {
+    fn new() -> Interner {
+        let mut interner = Interner {
+            ids: HashMap::new(),
+            names: vec![],
+        };
+
+        for name in PREDEFINED {
+            interner.intern(name);
+        }
+
+        interner
+    }
+
+    fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(symbol) = self.ids.get(name) {
+            return *symbol;
+        }
+
+        let symbol = Symbol(self.names.len() as u32);
+        let name: Rc<str> = Rc::from(name);
+        self.names.push(name.clone());
+        self.ids.insert(name, symbol);
+        symbol
+    }
+}
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner::new());
+}
+
+impl Symbol {
+    pub fn intern(name: &str) -> Symbol {
+        INTERNER.with(|interner| interner.borrow_mut().intern(name))
+    }
+
+    pub fn name(&self) -> Rc<str> {
+        INTERNER.with(|interner| interner.borrow().names[self.0 as usize].clone())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == EMPTY
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Symbol({}, {:?})", self.0, self.name())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn intern_is_exact() {
+        let a = Symbol::intern("counter");
+        assert_eq!(a, Symbol::intern("counter"));
+        assert_ne!(a, Symbol::intern("Counter"));
+        assert_eq!(&*a.name(), "counter");
+    }
+
+    #[test]
+    fn predefined() {
+        assert_eq!(Symbol::intern(""), EMPTY);
+        assert_eq!(Symbol::intern("this"), THIS);
+        assert_eq!(Symbol::intern("super"), SUPER);
+        assert_eq!(Symbol::intern("init"), INIT);
+        assert!(EMPTY.is_empty());
+    }
+}
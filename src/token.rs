@@ -6,13 +6,18 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
+    Colon,
     Dot,
     Minus,
     Plus,
     SemiColon,
     Slash,
     Star,
+    Percent,
+    TildeSlash,
 
     Bang,
     BangEqual,
@@ -25,10 +30,13 @@ pub enum TokenType {
 
     Identifier(String),
     Str(String),
-    Number(f32),
+    Number(f64),
+    Int(i64),
 
     And,
+    Break,
     Class,
+    Continue,
     Else,
     False,
     Fun,
@@ -47,30 +55,56 @@ pub enum TokenType {
     Eof,
 }
 
+// Byte offsets into the source, 'end' is exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Token {
     pub token_type: TokenType,
     pub line: u32,
+    // counted in characters from 1, 0 when the token wasn't scanned from source
+    pub column: u32,
+    pub span: Span,
 }
 
 impl Token {
+    // tokens without a position, for building token streams by hand
+    #[cfg(test)]
     pub fn new(token_type: TokenType, line: u32) -> Token {
-        Token { token_type, line }
+        Token::new_at(token_type, line, 0, Span::default())
+    }
+
+    pub fn new_at(token_type: TokenType, line: u32, column: u32, span: Span) -> Token {
+        Token {
+            token_type,
+            line,
+            column,
+            span,
+        }
     }
 
-    pub fn to_string(&self) -> String {
+    pub fn lexeme(&self) -> String {
         match &self.token_type {
             TokenType::LeftParen => "(",
             TokenType::RightParen => ")",
             TokenType::LeftBrace => "{",
             TokenType::RightBrace => "}",
+            TokenType::LeftBracket => "[",
+            TokenType::RightBracket => "]",
             TokenType::Comma => ",",
+            TokenType::Colon => ":",
             TokenType::Dot => ".",
             TokenType::Minus => "-",
             TokenType::Plus => "+",
             TokenType::SemiColon => ";",
             TokenType::Slash => "/",
             TokenType::Star => "*",
+            TokenType::Percent => "%",
+            TokenType::TildeSlash => "~/",
 
             TokenType::Bang => "!",
             TokenType::BangEqual => "!=",
@@ -84,9 +118,12 @@ impl Token {
             TokenType::Identifier(i) => return i.clone(),
             TokenType::Str(s) => return s.clone(),
             TokenType::Number(n) => return n.to_string(),
+            TokenType::Int(n) => return n.to_string(),
 
             TokenType::And => "and",
+            TokenType::Break => "break",
             TokenType::Class => "class",
+            TokenType::Continue => "continue",
             TokenType::Else => "else",
             TokenType::False => "false",
             TokenType::Fun => "fun",
@@ -0,0 +1,240 @@
+// Runs every .lox file under tests/lox and checks it against the
+// annotations in its comments, in the style of the Crafting Interpreters
+// test suite:
+//
+// print 1 + 2; // expect: 3
+// print -nil;  // expect runtime error: Unary negate expected number
+// var a = ;    // Error: Expected primary expression, found ;
+// // [line 7] Error: Expected '}' after block, found EOF
+//
+// Output lines are compared without trailing whitespace. A file that expects
+// compile errors must report exactly those errors, in order, and nothing runs.
+
+use rlox_tree_walker::error::ErrorDetail;
+use rlox_tree_walker::{Lox, LoxError, OutputBuffer};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const EXPECT: &str = "// expect: ";
+const EXPECT_RUNTIME_ERROR: &str = "// expect runtime error: ";
+
+#[derive(Debug, Default)]
+struct Expectations {
+    output: Vec<String>,
+    // line and message
+    runtime_error: Option<(u32, String)>,
+    compile_errors: Vec<(u32, String)>,
+}
+
+fn parse_expectations(source: &str) -> Expectations {
+    let mut expectations = Expectations::default();
+    for (i, line) in source.lines().enumerate() {
+        let line_number = i as u32 + 1;
+
+        if let Some(index) = line.find(EXPECT) {
+            let output = &line[index + EXPECT.len()..];
+            expectations.output.push(output.trim_end().to_string());
+        } else if let Some(index) = line.find(EXPECT_RUNTIME_ERROR) {
+            let message = &line[index + EXPECT_RUNTIME_ERROR.len()..];
+            expectations.runtime_error = Some((line_number, message.trim_end().to_string()));
+        } else if let Some(index) = line.find("// [line ") {
+            let rest = &line[index + "// [line ".len()..];
+            let (number, rest) = rest.split_once(']').expect("unterminated [line N]");
+            let message = rest
+                .trim_start()
+                .strip_prefix("Error: ")
+                .expect("expected 'Error: ' after [line N]");
+            expectations.compile_errors.push((
+                number.parse().expect("invalid line number"),
+                message.trim_end().to_string(),
+            ));
+        } else if let Some(index) = line.find("// Error: ") {
+            let message = &line[index + "// Error: ".len()..];
+            expectations
+                .compile_errors
+                .push((line_number, message.trim_end().to_string()));
+        }
+    }
+
+    expectations
+}
+
+// the line an error points at, from its span when it doesn't record one
+fn error_line(detail: &ErrorDetail, source: &str) -> u32 {
+    match (detail.line, detail.span) {
+        (Some(line), _) => line,
+        (None, Some(span)) if span.end > 0 && span.start <= source.len() => {
+            source[..span.start].matches('\n').count() as u32 + 1
+        }
+        _ => 0,
+    }
+}
+
+fn describe(errors: &[(u32, String)]) -> Vec<String> {
+    errors
+        .iter()
+        .map(|(line, message)| format!("[line {}] {}", line, message))
+        .collect()
+}
+
+// A line diff of the expected against the actual lines, from their longest
+// common subsequence
+fn diff(expected: &[String], actual: &[String]) -> String {
+    let mut lengths = vec![vec![0; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            lengths[i][j] = if expected[i] == actual[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            let _ = writeln!(out, "   {}", expected[i]);
+            i += 1;
+            j += 1;
+        } else if j < actual.len()
+            && (i == expected.len() || lengths[i][j + 1] >= lengths[i + 1][j])
+        {
+            let _ = writeln!(out, " + {}", actual[j]);
+            j += 1;
+        } else {
+            let _ = writeln!(out, " - {}", expected[i]);
+            i += 1;
+        }
+    }
+
+    out
+}
+
+// Runs one file, returning a description of how it failed
+fn run_file(path: &Path) -> Result<(), String> {
+    let source = std::fs::read_to_string(path).map_err(|e| format!("can't read: {}", e))?;
+    let expectations = parse_expectations(&source);
+
+    let output = OutputBuffer::new();
+    let mut lox = Lox::new();
+    lox.set_output(output.clone());
+    lox.set_error_output(std::io::sink());
+    let result = lox.run(&source);
+
+    let mut failures = String::new();
+
+    let actual_output: Vec<String> = output
+        .contents()
+        .lines()
+        .map(|line| line.trim_end().to_string())
+        .collect();
+    if actual_output != expectations.output {
+        let _ = write!(
+            failures,
+            "output differs (- expected, + actual):\n{}",
+            diff(&expectations.output, &actual_output)
+        );
+    }
+
+    let (runtime_error, compile_errors) = match result {
+        Ok(_) => (None, vec![]),
+        Err(errors) => match errors.as_slice() {
+            [LoxError::Runtime(detail)] => (
+                Some((error_line(detail, &source), detail.message.clone())),
+                vec![],
+            ),
+            _ => (
+                None,
+                errors
+                    .iter()
+                    .map(|e| (error_line(e.detail(), &source), e.detail().message.clone()))
+                    .collect(),
+            ),
+        },
+    };
+
+    if runtime_error != expectations.runtime_error {
+        let _ = writeln!(
+            failures,
+            "runtime error differs:\n - {:?}\n + {:?}",
+            expectations.runtime_error, runtime_error
+        );
+    }
+
+    if compile_errors != expectations.compile_errors {
+        let _ = write!(
+            failures,
+            "compile errors differ (- expected, + actual):\n{}",
+            diff(
+                &describe(&expectations.compile_errors),
+                &describe(&compile_errors)
+            )
+        );
+    }
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(failures)
+    }
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let entries = std::fs::read_dir(dir).unwrap_or_else(|e| panic!("{}: {}", dir.display(), e));
+    for entry in entries {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            collect_files(&path, files);
+        } else if path.extension().is_some_and(|extension| extension == "lox") {
+            files.push(path);
+        }
+    }
+}
+
+#[test]
+fn conformance() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("lox");
+    let mut files = vec![];
+    collect_files(&root, &mut files);
+    files.sort();
+    assert!(!files.is_empty(), "no tests found in {}", root.display());
+
+    let workers = std::thread::available_parallelism().map_or(4, |n| n.get());
+    let next = Mutex::new(files.iter());
+    let failures = Mutex::new(vec![]);
+
+    // Lox sessions aren't Send, so each worker makes its own for every file
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let path = match next.lock().unwrap().next() {
+                    Some(path) => path,
+                    None => break,
+                };
+
+                if let Err(failure) = run_file(path) {
+                    let name = path.strip_prefix(&root).unwrap_or(path);
+                    failures
+                        .lock()
+                        .unwrap()
+                        .push(format!("FAIL {}\n{}", name.display(), failure));
+                }
+            });
+        }
+    });
+
+    let mut failures = failures.into_inner().unwrap();
+    failures.sort();
+    assert!(
+        failures.is_empty(),
+        "{} of {} lox tests failed\n\n{}",
+        failures.len(),
+        files.len(),
+        failures.join("\n")
+    );
+}